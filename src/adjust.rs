@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+fn default_one() -> f32 {
+    1.0
+}
+
+fn default_white() -> u8 {
+    255
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+pub struct Adjustments {
+    #[serde(default)]
+    pub brightness: f32,
+    #[serde(default = "default_one")]
+    pub contrast: f32,
+    #[serde(default = "default_one")]
+    pub saturation: f32,
+    #[serde(default)]
+    pub hue: f32,
+    #[serde(default)]
+    pub black: u8,
+    #[serde(default = "default_white")]
+    pub white: u8,
+    #[serde(default = "default_one")]
+    pub gamma: f32,
+    #[serde(default)]
+    pub invert: bool,
+}
+impl Adjustments {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-1.0..=1.0).contains(&self.brightness) {
+            return Err(format!("Brightness adjustment must be between -1.0 and 1.0, got {}", self.brightness));
+        }
+
+        if !(0.0..=4.0).contains(&self.contrast) {
+            return Err(format!("Contrast must be between 0.0 and 4.0, got {}", self.contrast));
+        }
+
+        if !(0.0..=4.0).contains(&self.saturation) {
+            return Err(format!("Saturation must be between 0.0 and 4.0, got {}", self.saturation));
+        }
+
+        if !(-360.0..=360.0).contains(&self.hue) {
+            return Err(format!("Hue rotation must be between -360 and 360 degrees, got {}", self.hue));
+        }
+
+        if self.black >= self.white {
+            return Err(format!("Black level {} must be below white level {}", self.black, self.white));
+        }
+
+        if !(0.1..=10.0).contains(&self.gamma) {
+            return Err(format!("Levels gamma must be between 0.1 and 10.0, got {}", self.gamma));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::default()
+    }
+
+    fn hue_matrix(&self) -> [[f32; 3]; 3] {
+        let (sin, cos) = self.hue.to_radians().sin_cos();
+
+        [
+            [0.213 + cos * 0.787 - sin * 0.213, 0.715 - cos * 0.715 - sin * 0.715, 0.072 - cos * 0.072 + sin * 0.928],
+            [0.213 - cos * 0.213 + sin * 0.143, 0.715 + cos * 0.285 + sin * 0.140, 0.072 - cos * 0.072 - sin * 0.283],
+            [0.213 - cos * 0.213 - sin * 0.787, 0.715 - cos * 0.715 + sin * 0.715, 0.072 + cos * 0.928 + sin * 0.072],
+        ]
+    }
+
+    pub fn apply(&self, img: &mut image::RgbaImage) {
+        if self.is_identity() {
+            return;
+        }
+
+        let black = self.black as f32 / 255.0;
+        let range = (self.white - self.black) as f32 / 255.0;
+        let hue = self.hue_matrix();
+
+        for pixel in img.pixels_mut() {
+            let mut rgb = [0, 1, 2].map(|c| {
+                let value = ((pixel[c] as f32 / 255.0 - black) / range).clamp(0.0, 1.0).powf(1.0 / self.gamma);
+                (value + self.brightness - 0.5) * self.contrast + 0.5
+            });
+
+            let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+            rgb = rgb.map(|value| luma + (value - luma) * self.saturation);
+
+            if self.hue != 0.0 {
+                rgb = hue.map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]);
+            }
+
+            if self.invert {
+                rgb = rgb.map(|value| 1.0 - value);
+            }
+
+            for c in 0..3 {
+                pixel[c] = (rgb[c].clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+}
+impl std::default::Default for Adjustments {
+    fn default() -> Self {
+        Adjustments{
+            brightness: 0.0,
+            contrast: default_one(),
+            saturation: default_one(),
+            hue: 0.0,
+            black: 0,
+            white: default_white(),
+            gamma: default_one(),
+            invert: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AdjustQuery {
+    pub brightness: Option<f32>,
+    pub contrast: Option<f32>,
+    pub saturation: Option<f32>,
+    pub hue: Option<f32>,
+    pub black: Option<u8>,
+    pub white: Option<u8>,
+    pub gamma: Option<f32>,
+    pub invert: Option<bool>,
+}
+impl AdjustQuery {
+    pub fn apply(&self, adjust: Adjustments) -> Result<Adjustments, String> {
+        let adjust = Adjustments{
+            brightness: self.brightness.unwrap_or(adjust.brightness),
+            contrast: self.contrast.unwrap_or(adjust.contrast),
+            saturation: self.saturation.unwrap_or(adjust.saturation),
+            hue: self.hue.unwrap_or(adjust.hue),
+            black: self.black.unwrap_or(adjust.black),
+            white: self.white.unwrap_or(adjust.white),
+            gamma: self.gamma.unwrap_or(adjust.gamma),
+            invert: self.invert.unwrap_or(adjust.invert),
+        };
+
+        adjust.validate()?;
+        Ok(adjust)
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize)]
+pub struct BrightnessRequest {
+    pub brightness: f32,
+    #[serde(default)]
+    pub fade_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct BrightnessResponse {
+    pub brightness: f32,
+}
+
+pub struct Brightness {
+    from: f32,
+    to: f32,
+    start: Instant,
+    duration: Duration,
+}
+impl Brightness {
+    pub fn new(level: f32) -> Self {
+        Brightness{
+            from: level,
+            to: level,
+            start: Instant::now(),
+            duration: Duration::ZERO,
+        }
+    }
+
+    pub fn level(&self, now: Instant) -> f32 {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return self.to;
+        }
+
+        let t = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        self.from + (self.to - self.from) * t
+    }
+
+    pub fn fade_to(&mut self, level: f32, duration: Duration, now: Instant) {
+        self.from = self.level(now);
+        self.to = level;
+        self.start = now;
+        self.duration = duration;
+    }
+}
+
+pub fn apply_brightness(frame: &mut [f32], level: f32) {
+    if level >= 1.0 {
+        return;
+    }
+
+    for value in frame.iter_mut() {
+        *value *= level;
+    }
+}
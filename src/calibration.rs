@@ -0,0 +1,82 @@
+use serde::Deserialize;
+
+fn default_gamma() -> f32 {
+    1.0
+}
+
+fn default_white_balance() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CalibrationConfig {
+    #[serde(default = "default_gamma")]
+    pub gamma: f32,
+    pub lut: Option<Vec<u8>>,
+    #[serde(default = "default_white_balance")]
+    pub white_balance: [f32; 3],
+}
+impl CalibrationConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.gamma.is_finite() || self.gamma <= 0.0 {
+            return Err(format!("Gamma must be greater than zero, got {}", self.gamma));
+        }
+
+        if let Some(lut) = &self.lut
+            && lut.len() != 256 {
+            return Err(format!("Lookup table must have 256 entries, got {}", lut.len()));
+        }
+
+        if self.white_balance.iter().any(|scale| !(0.0..=1.0).contains(scale)) {
+            return Err(String::from("White balance factors must be between 0.0 and 1.0"));
+        }
+
+        Ok(())
+    }
+}
+impl std::default::Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig{
+            gamma: default_gamma(),
+            lut: None,
+            white_balance: default_white_balance(),
+        }
+    }
+}
+
+pub struct Calibration {
+    gamma: f32,
+    lut: Option<Vec<f32>>,
+    white_balance: [f32; 3],
+}
+impl Calibration {
+    pub fn new(cfg: &CalibrationConfig) -> Self {
+        Calibration{
+            gamma: cfg.gamma,
+            lut: cfg.lut.as_ref().map(|lut| lut.iter().map(|value| *value as f32).collect()),
+            white_balance: cfg.white_balance,
+        }
+    }
+
+    fn correct(&self, value: f32) -> f32 {
+        let value = value.clamp(0.0, 255.0);
+
+        match &self.lut {
+            Some(lut) => {
+                let idx = (value.floor() as usize).min(254);
+                let frac = value - idx as f32;
+
+                lut[idx] + (lut[idx + 1] - lut[idx]) * frac
+            },
+            None => (value / 255.0).powf(self.gamma) * 255.0,
+        }
+    }
+
+    pub fn apply(&self, frame: &mut [f32]) {
+        for pixel in frame.chunks_exact_mut(3) {
+            for (channel, value) in pixel.iter_mut().enumerate() {
+                *value = self.correct(*value) * self.white_balance[channel];
+            }
+        }
+    }
+}
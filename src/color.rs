@@ -0,0 +1,168 @@
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    #[default]
+    Rgb,
+    Hsv,
+    LinearRgb,
+    Oklab,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+impl Easing {
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+fn to_unit(rgb: [u8; 3]) -> [f32; 3] {
+    rgb.map(|value| value as f32 / 255.0)
+}
+
+fn from_unit(rgb: [f32; 3]) -> [u8; 3] {
+    rgb.map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.max(0.0).powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn rgb_to_hsv(rgb: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = to_unit(rgb);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let sat = if max == 0.0 { 0.0 } else { delta / max };
+
+    [hue, sat, max]
+}
+
+pub fn hsv_to_rgb(hsv: [f32; 3]) -> [u8; 3] {
+    let [hue, sat, val] = hsv;
+    let hue = hue.rem_euclid(360.0);
+
+    let chroma = val * sat;
+    let x = chroma * (1.0 - ((hue / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = val - chroma;
+
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    from_unit([r + m, g + m, b + m])
+}
+
+fn rgb_to_oklab(rgb: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = to_unit(rgb).map(srgb_to_linear);
+
+    let l = (0.412221 * r + 0.536333 * g + 0.051446 * b).cbrt();
+    let m = (0.211903 * r + 0.680699 * g + 0.107397 * b).cbrt();
+    let s = (0.088302 * r + 0.281719 * g + 0.629979 * b).cbrt();
+
+    [
+        0.210454 * l + 0.793618 * m - 0.004072 * s,
+        1.977999 * l - 2.42859 * m + 0.450594 * s,
+        0.025904 * l + 0.782772 * m - 0.808676 * s,
+    ]
+}
+
+fn oklab_to_rgb(lab: [f32; 3]) -> [u8; 3] {
+    let [l, a, b] = lab;
+
+    let l_ = (l + 0.396338 * a + 0.215804 * b).powi(3);
+    let m_ = (l - 0.105561 * a - 0.063854 * b).powi(3);
+    let s_ = (l - 0.089484 * a - 1.29149 * b).powi(3);
+
+    let rgb = [
+        4.07674 * l_ - 3.30771 * m_ + 0.23097 * s_,
+        -1.26844 * l_ + 2.60976 * m_ - 0.341319 * s_,
+        -0.004196 * l_ - 0.703419 * m_ + 1.70761 * s_,
+    ];
+
+    from_unit(rgb.map(linear_to_srgb))
+}
+
+fn lerp(from: [f32; 3], to: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        from[0] + (to[0] - from[0]) * t,
+        from[1] + (to[1] - from[1]) * t,
+        from[2] + (to[2] - from[2]) * t,
+    ]
+}
+
+fn lerp_hsv(from: [f32; 3], to: [f32; 3], t: f32) -> [f32; 3] {
+    // Greys have no meaningful hue, so borrow it from the other end instead of
+    // sweeping through unrelated colors.
+    let from_hue = if from[1] == 0.0 { to[0] } else { from[0] };
+    let to_hue = if to[1] == 0.0 { from_hue } else { to[0] };
+
+    let mut delta = to_hue - from_hue;
+    if delta > 180.0 {
+        delta -= 360.0;
+    } else if delta < -180.0 {
+        delta += 360.0;
+    }
+
+    [
+        from_hue + delta * t,
+        from[1] + (to[1] - from[1]) * t,
+        from[2] + (to[2] - from[2]) * t,
+    ]
+}
+
+pub fn interpolate(from: [u8; 3], to: [u8; 3], t: f32, space: ColorSpace) -> [u8; 3] {
+    match space {
+        ColorSpace::Rgb => from_unit(lerp(to_unit(from), to_unit(to), t)),
+        ColorSpace::Hsv => hsv_to_rgb(lerp_hsv(rgb_to_hsv(from), rgb_to_hsv(to), t)),
+        ColorSpace::LinearRgb => {
+            let from = to_unit(from).map(srgb_to_linear);
+            let to = to_unit(to).map(srgb_to_linear);
+            from_unit(lerp(from, to, t).map(linear_to_srgb))
+        },
+        ColorSpace::Oklab => oklab_to_rgb(lerp(rgb_to_oklab(from), rgb_to_oklab(to), t)),
+    }
+}
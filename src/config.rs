@@ -1,25 +1,277 @@
-use serde::Deserialize;
-use serde_json;
+use serde::{Deserialize, Deserializer};
 
 use std::fs::File;
 use std::path::Path;
 
+use crate::calibration::CalibrationConfig;
+use crate::layout::PixelLayout;
+use crate::power::PowerConfig;
+
 fn default_templates() -> String {
     String::from("templates")
 }
 
+fn default_state() -> String {
+    String::from("state.json")
+}
+
+fn default_baud_rate() -> u32 {
+    115_200
+}
+
+fn default_data_bits() -> u8 {
+    8
+}
+
+fn default_write_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_ack_timeout_ms() -> u64 {
+    200
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    #[default]
+    None,
+    Software,
+    Hardware,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineControl {
+    #[default]
+    Unchanged,
+    High,
+    Low,
+    Pulse,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Raw,
+    Framed,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SerialConfig {
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+    #[serde(default = "default_data_bits")]
+    pub data_bits: u8,
+    #[serde(default)]
+    pub parity: Parity,
+    #[serde(default)]
+    pub flow_control: FlowControl,
+    #[serde(default = "default_write_timeout_ms")]
+    pub write_timeout_ms: u64,
+    #[serde(default)]
+    pub dtr: LineControl,
+    #[serde(default)]
+    pub rts: LineControl,
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default = "default_ack_timeout_ms")]
+    pub ack_timeout_ms: u64,
+}
+impl SerialConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.baud_rate == 0 {
+            return Err(String::from("Serial baud rate must be greater than zero"));
+        }
+
+        if !(5..=8).contains(&self.data_bits) {
+            return Err(format!("Serial data bits must be between 5 and 8, got {}", self.data_bits));
+        }
+
+        if self.write_timeout_ms == 0 {
+            return Err(String::from("Serial write timeout must be greater than zero"));
+        }
+
+        if self.flow_control == FlowControl::Hardware && self.rts != LineControl::Unchanged {
+            return Err(String::from("RTS cannot be controlled manually with hardware flow control"));
+        }
+
+        if self.protocol == Protocol::Framed && self.ack_timeout_ms == 0 {
+            return Err(String::from("Acknowledgement timeout must be greater than zero"));
+        }
+
+        Ok(())
+    }
+}
+impl std::default::Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig{
+            baud_rate: default_baud_rate(),
+            data_bits: default_data_bits(),
+            parity: Parity::default(),
+            flow_control: FlowControl::default(),
+            write_timeout_ms: default_write_timeout_ms(),
+            dtr: LineControl::default(),
+            rts: LineControl::default(),
+            protocol: Protocol::default(),
+            ack_timeout_ms: default_ack_timeout_ms(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputKind {
+    Serial,
+    Null,
+    File,
+    Memory,
+}
+impl OutputKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputKind::Serial => "serial",
+            OutputKind::Null => "null",
+            OutputKind::File => "file",
+            OutputKind::Memory => "memory",
+        }
+    }
+}
+
+fn deserialize_usb_id<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+where
+    D: Deserializer<'de>
+{
+    let id: Option<String> = Option::deserialize(deserializer)?;
+
+    match id {
+        Some(id) => match u16::from_str_radix(id.trim_start_matches("0x"), 16) {
+            Ok(id) => Ok(Some(id)),
+            Err(_) => Err(serde::de::Error::custom(format!("invalid USB id {:?}, expected four hex digits", id))),
+        },
+        None => Ok(None),
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct UsbMatch {
+    #[serde(default, deserialize_with = "deserialize_usb_id")]
+    pub vid: Option<u16>,
+    #[serde(default, deserialize_with = "deserialize_usb_id")]
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+}
+impl UsbMatch {
+    fn validate(&self) -> Result<(), String> {
+        if self.vid.is_none() && self.pid.is_none() && self.serial_number.is_none() && self.manufacturer.is_none() {
+            return Err(String::from("USB device match needs at least one of vid, pid, serial_number or manufacturer"));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct MatrixConfig {
+    pub width: u16,
+    pub height: u16,
+}
+
 #[derive(Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub calibration: CalibrationConfig,
     pub device: Option<String>,
+    #[serde(default)]
+    pub dither: bool,
     pub host: String,
+    #[serde(default)]
+    pub layout: PixelLayout,
+    pub led_map: Option<String>,
+    pub matrix: Option<MatrixConfig>,
+    pub output: Option<OutputKind>,
+    #[serde(default)]
+    pub power: PowerConfig,
+    pub record: Option<String>,
+    pub usb: Option<UsbMatch>,
+    #[serde(default)]
+    pub serial: SerialConfig,
+    #[serde(default = "default_state")]
+    pub state: String,
     #[serde(default = "default_templates")]
     pub templates: String,
 }
+impl Config {
+    pub fn output_kind(&self) -> OutputKind {
+        match self.output {
+            Some(output) => output,
+            None if self.device.is_some() || self.usb.is_some() => OutputKind::Serial,
+            None => OutputKind::Null,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(matrix) = &self.matrix
+            && (matrix.width == 0 || matrix.height == 0) {
+            return Err(String::from("Matrix width and height must be greater than zero"));
+        }
+
+        if let Err(e) = self.layout.validate() {
+            return Err(format!("Invalid layout: {}", e));
+        }
+
+        if let Err(e) = self.calibration.validate() {
+            return Err(format!("Invalid calibration: {}", e));
+        }
+
+        if let Err(e) = self.power.validate() {
+            return Err(format!("Invalid power configuration: {}", e));
+        }
+
+        if let Err(e) = self.serial.validate() {
+            return Err(format!("Invalid serial configuration: {}", e));
+        }
+
+        if let Some(usb) = &self.usb {
+            if self.device.is_some() {
+                return Err(String::from("Only one of device and usb can be configured"));
+            }
+
+            if let Err(e) = usb.validate() {
+                return Err(format!("Invalid USB configuration: {}", e));
+            }
+        }
+
+        Ok(())
+    }
+}
 impl std::default::Default for Config {
     fn default() -> Self {
         Config{
+            calibration: CalibrationConfig::default(),
             device: None,
+            dither: false,
             host: String::from("127.0.0.1:5000"),
+            layout: PixelLayout::default(),
+            led_map: None,
+            matrix: None,
+            output: None,
+            power: PowerConfig::default(),
+            record: None,
+            usb: None,
+            serial: SerialConfig::default(),
+            state: default_state(),
             templates: String::from("templates"),
         }
     }
@@ -36,8 +288,12 @@ pub fn read_config(path: &Path) -> Result<Config, String> {
         Err(e) => return Err(format!("Cannot open configuration file: {}", e)),
     };
 
-    match serde_json::from_reader(fh) {
-        Ok(cfg) => Ok(cfg),
-        Err(e) => Err(format!("Cannot parse configuration file: {}", e)),
-    }
+    let cfg: Config = match serde_json::from_reader(fh) {
+        Ok(cfg) => cfg,
+        Err(e) => return Err(format!("Cannot parse configuration file: {}", e)),
+    };
+
+    cfg.validate()?;
+
+    Ok(cfg)
 }
@@ -1,18 +1,242 @@
-use std::time::Duration;
+use serde::Serialize;
 
-pub fn open_device(path: &str) -> Result<Box<dyn serialport::SerialPort>, String> {
-    let builder = serialport::new(path, 115_200)
-        .timeout(Duration::from_secs(1))
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use crate::config::{FlowControl, LineControl, Parity, Protocol, SerialConfig, UsbMatch};
+use crate::protocol::{self, DeviceInfo};
+
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(10);
+const LINE_PULSE: Duration = Duration::from_millis(100);
+const HOTPLUG_POLL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Serialize)]
+pub struct DeviceStatus {
+    pub output: &'static str,
+    pub device: Option<String>,
+    pub connected: bool,
+    pub info: Option<DeviceInfo>,
+    pub last_error: Option<String>,
+}
+
+pub type SharedDeviceStatus = Arc<Mutex<DeviceStatus>>;
+
+#[derive(Clone, Serialize)]
+pub struct PortInfo {
+    pub path: String,
+    pub kind: &'static str,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub selected: bool,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct PortWatch {
+    pub ports: Vec<PortInfo>,
+    pub selected: Option<String>,
+}
+
+pub type SharedPortWatch = Arc<Mutex<PortWatch>>;
+
+#[derive(Clone)]
+pub enum DeviceSelector {
+    Path(String),
+    Usb(UsbMatch),
+}
+impl DeviceSelector {
+    fn matches(&self, port: &serialport::SerialPortInfo) -> bool {
+        match self {
+            DeviceSelector::Path(path) => port.port_name == *path,
+            DeviceSelector::Usb(usb) => match &port.port_type {
+                serialport::SerialPortType::UsbPort(info) => {
+                    usb.vid.is_none_or(|vid| vid == info.vid)
+                        && usb.pid.is_none_or(|pid| pid == info.pid)
+                        && usb.serial_number.as_ref().is_none_or(|serial| info.serial_number.as_ref() == Some(serial))
+                        && usb.manufacturer.as_ref().is_none_or(|manufacturer| info.manufacturer.as_ref() == Some(manufacturer))
+                },
+                _ => false,
+            },
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            DeviceSelector::Path(path) => path.clone(),
+            DeviceSelector::Usb(usb) => {
+                let mut parts = Vec::new();
+                if let Some(vid) = usb.vid {
+                    parts.push(format!("vid={:04x}", vid));
+                }
+                if let Some(pid) = usb.pid {
+                    parts.push(format!("pid={:04x}", pid));
+                }
+                if let Some(serial_number) = &usb.serial_number {
+                    parts.push(format!("serial={}", serial_number));
+                }
+                if let Some(manufacturer) = &usb.manufacturer {
+                    parts.push(format!("manufacturer={}", manufacturer));
+                }
+
+                format!("USB device {}", parts.join(" "))
+            },
+        }
+    }
+}
+
+fn port_info(port: serialport::SerialPortInfo, selected: bool) -> PortInfo {
+    let mut info = PortInfo{
+        path: port.port_name,
+        kind: "unknown",
+        vid: None,
+        pid: None,
+        serial_number: None,
+        manufacturer: None,
+        product: None,
+        selected,
+    };
+
+    match port.port_type {
+        serialport::SerialPortType::UsbPort(usb) => {
+            info.kind = "usb";
+            info.vid = Some(usb.vid);
+            info.pid = Some(usb.pid);
+            info.serial_number = usb.serial_number;
+            info.manufacturer = usb.manufacturer;
+            info.product = usb.product;
+        },
+        serialport::SerialPortType::PciPort => info.kind = "pci",
+        serialport::SerialPortType::BluetoothPort => info.kind = "bluetooth",
+        serialport::SerialPortType::Unknown => (),
+    }
+
+    info
+}
+
+pub fn scan_ports(selector: Option<&DeviceSelector>) -> PortWatch {
+    let ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(e) => {
+            error!("Failed to enumerate serial ports: {}", e);
+            Vec::new()
+        },
+    };
+
+    let mut watch = PortWatch::default();
+    for port in ports {
+        let selected = watch.selected.is_none() && selector.is_some_and(|selector| selector.matches(&port));
+        if selected {
+            watch.selected = Some(port.port_name.clone());
+        }
+
+        watch.ports.push(port_info(port, selected));
+    }
+
+    if let Some(DeviceSelector::Path(path)) = selector
+        && watch.selected.is_none()
+        && std::path::Path::new(path).exists() {
+        watch.selected = Some(path.clone());
+    }
+
+    watch
+}
+
+pub fn watch_ports(selector: DeviceSelector) -> SharedPortWatch {
+    let watch = Arc::new(Mutex::new(scan_ports(Some(&selector))));
+
+    let shared_watch = watch.clone();
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(HOTPLUG_POLL);
+
+            let scanned = scan_ports(Some(&selector));
+            let mut watch = shared_watch.lock().unwrap();
+            if watch.selected != scanned.selected {
+                match &scanned.selected {
+                    Some(path) => info!("{} appeared at {}", selector.describe(), path),
+                    None => info!("{} disappeared", selector.describe()),
+                }
+            }
+
+            *watch = scanned;
+        }
+    });
+
+    watch
+}
+
+fn data_bits(bits: u8) -> serialport::DataBits {
+    match bits {
+        5 => serialport::DataBits::Five,
+        6 => serialport::DataBits::Six,
+        7 => serialport::DataBits::Seven,
+        _ => serialport::DataBits::Eight,
+    }
+}
+
+fn parity(parity: Parity) -> serialport::Parity {
+    match parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Odd => serialport::Parity::Odd,
+        Parity::Even => serialport::Parity::Even,
+    }
+}
+
+fn flow_control(flow_control: FlowControl) -> serialport::FlowControl {
+    match flow_control {
+        FlowControl::None => serialport::FlowControl::None,
+        FlowControl::Software => serialport::FlowControl::Software,
+        FlowControl::Hardware => serialport::FlowControl::Hardware,
+    }
+}
+
+fn apply_line_control<F>(control: LineControl, mut write_line: F) -> serialport::Result<()>
+where
+    F: FnMut(bool) -> serialport::Result<()>
+{
+    match control {
+        LineControl::Unchanged => Ok(()),
+        LineControl::High => write_line(true),
+        LineControl::Low => write_line(false),
+        LineControl::Pulse => {
+            write_line(false)?;
+            std::thread::sleep(LINE_PULSE);
+            write_line(true)
+        },
+    }
+}
+
+pub fn open_device(path: &str, serial: &SerialConfig) -> Result<Box<dyn serialport::SerialPort>, String> {
+    let builder = serialport::new(path, serial.baud_rate)
+        .data_bits(data_bits(serial.data_bits))
+        .parity(parity(serial.parity))
+        .flow_control(flow_control(serial.flow_control))
+        .timeout(Duration::from_millis(serial.write_timeout_ms))
         .open();
 
-    match builder {
-        Ok(device) => Ok(device),
-        Err(e) => Err(format!("Cannot open serial port: {}", e))
+    let mut device = match builder {
+        Ok(device) => device,
+        Err(e) => return Err(format!("Cannot open serial port: {}", e))
+    };
+
+    if let Err(e) = apply_line_control(serial.dtr, |level| device.write_data_terminal_ready(level)) {
+        return Err(format!("Cannot set DTR: {}", e));
     }
+
+    if let Err(e) = apply_line_control(serial.rts, |level| device.write_request_to_send(level)) {
+        return Err(format!("Cannot set RTS: {}", e));
+    }
+
+    Ok(device)
 }
 
 pub fn upload_frame(device: &mut Box<dyn serialport::SerialPort>, frame: &[u8]) -> Result<(), String> {
-    if let Err(e) = device.write(&frame) {
+    if let Err(e) = device.write(frame) {
         return Err(e.to_string());
     }
 
@@ -22,3 +246,149 @@ pub fn upload_frame(device: &mut Box<dyn serialport::SerialPort>, frame: &[u8])
 
     Ok(())
 }
+
+pub struct Connection {
+    selector: DeviceSelector,
+    watch: SharedPortWatch,
+    serial: SerialConfig,
+    port: Option<Box<dyn serialport::SerialPort>>,
+    port_path: Option<String>,
+    seq: u16,
+    backoff: Duration,
+    retry_at: Instant,
+    status: SharedDeviceStatus,
+}
+impl Connection {
+    pub fn new(selector: DeviceSelector, watch: SharedPortWatch, serial: SerialConfig, status: SharedDeviceStatus) -> Self {
+        let mut connection = Connection{
+            selector,
+            watch,
+            serial,
+            port: None,
+            port_path: None,
+            seq: 0,
+            backoff: RECONNECT_BACKOFF_MIN,
+            retry_at: Instant::now(),
+            status,
+        };
+        connection.reconnect();
+
+        connection
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.port.is_some()
+    }
+
+    pub fn reconnect(&mut self) -> bool {
+        let selected = self.watch.lock().unwrap().selected.clone();
+
+        if self.port.is_some() {
+            if selected.is_some() && selected == self.port_path {
+                return true;
+            }
+
+            self.disconnect(format!("{} was unplugged", self.selector.describe()));
+        }
+        if Instant::now() < self.retry_at {
+            return false;
+        }
+
+        let result = match selected {
+            Some(path) => self.open(&path).map(|(port, info)| (path, port, info)),
+            None => Err(format!("{} not found", self.selector.describe())),
+        };
+
+        match result {
+            Ok((path, port, info)) => {
+                match &info {
+                    Some(info) => info!("Connected to device {} ({}x{}, firmware {})", path, info.width, info.height, info.firmware),
+                    None => info!("Connected to device {}", path),
+                }
+
+                self.port = Some(port);
+                self.port_path = Some(path.clone());
+                self.seq = 0;
+                self.backoff = RECONNECT_BACKOFF_MIN;
+                {
+                    let mut status = self.status.lock().unwrap();
+                    status.device = Some(path);
+                    status.info = info;
+                }
+                self.set_status(true, None);
+
+                true
+            },
+            Err(e) => {
+                if self.status.lock().unwrap().last_error.as_ref() != Some(&e) {
+                    error!("Failed to open {}: {}", self.selector.describe(), e);
+                }
+
+                self.retry_at = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                self.set_status(false, Some(e));
+
+                false
+            },
+        }
+    }
+
+    fn disconnect(&mut self, reason: String) {
+        error!("Lost connection to device: {}", reason);
+
+        self.port = None;
+        self.port_path = None;
+        self.retry_at = Instant::now() + self.backoff;
+        self.set_status(false, Some(reason));
+    }
+
+    fn open(&self, path: &str) -> Result<(Box<dyn serialport::SerialPort>, Option<DeviceInfo>), String> {
+        let mut port = open_device(path, &self.serial)?;
+
+        match self.serial.protocol {
+            Protocol::Raw => Ok((port, None)),
+            Protocol::Framed => {
+                let timeout = Duration::from_millis(self.serial.ack_timeout_ms);
+                match protocol::handshake(&mut port, timeout) {
+                    Ok(info) => Ok((port, Some(info))),
+                    Err(e) => Err(format!("Handshake failed: {}", e)),
+                }
+            },
+        }
+    }
+
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        let port = match &mut self.port {
+            Some(port) => port,
+            None => return Err(format!("{} is disconnected", self.selector.describe())),
+        };
+
+        let result = match self.serial.protocol {
+            Protocol::Raw => upload_frame(port, frame),
+            Protocol::Framed => {
+                let timeout = Duration::from_millis(self.serial.ack_timeout_ms);
+                self.seq = self.seq.wrapping_add(1);
+
+                protocol::send_frame(port, self.seq, frame, timeout)
+            },
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.port = None;
+                self.port_path = None;
+                self.retry_at = Instant::now() + self.backoff;
+                self.set_status(false, Some(e.clone()));
+
+                Err(e)
+            },
+        }
+    }
+
+    fn set_status(&self, connected: bool, last_error: Option<String>) {
+        let mut status = self.status.lock().unwrap();
+        status.connected = connected;
+        status.last_error = last_error;
+    }
+}
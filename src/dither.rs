@@ -0,0 +1,43 @@
+use crate::frame::Frame;
+
+const RESIDUAL_EPSILON: f32 = 1.0 / 64.0;
+
+pub struct TemporalDither {
+    enabled: bool,
+    error: Vec<f32>,
+}
+impl TemporalDither {
+    pub fn new(enabled: bool) -> Self {
+        TemporalDither{ enabled, error: Vec::new() }
+    }
+
+    // Returns the quantized frame and whether it left a fractional remainder
+    // that later refreshes still have to spread over time.
+    pub fn quantize(&mut self, frame: &[f32]) -> (Frame, bool) {
+        if !self.enabled {
+            let output = frame.iter().map(|value| value.round().clamp(0.0, 255.0) as u8).collect();
+            return (output, false);
+        }
+
+        if self.error.len() != frame.len() {
+            self.error = vec![0.0; frame.len()];
+        }
+
+        let mut residual = false;
+        let mut output = Vec::with_capacity(frame.len());
+        for (value, error) in frame.iter().zip(self.error.iter_mut()) {
+            let value = value.clamp(0.0, 255.0);
+            if (value - value.round()).abs() > RESIDUAL_EPSILON {
+                residual = true;
+            }
+
+            let target = value + *error;
+            let quantized = target.round().clamp(0.0, 255.0);
+            *error = target - quantized;
+
+            output.push(quantized as u8);
+        }
+
+        (output, residual)
+    }
+}
@@ -0,0 +1,358 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use crate::color;
+use crate::frame::{Canvas, Frame};
+
+pub trait Effect: Send {
+    fn render(&self, canvas: &Canvas, elapsed: Duration) -> Frame;
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParamKind {
+    Number { min: f32, max: f32, default: f32 },
+    Color { default: [u8; 3] },
+}
+
+#[derive(Clone, Serialize)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub kind: ParamKind,
+}
+
+#[derive(Clone, Serialize)]
+pub struct EffectInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: Vec<ParamSpec>,
+}
+
+enum ParamValue {
+    Number(f32),
+    Color([u8; 3]),
+}
+
+struct Params {
+    values: HashMap<&'static str, ParamValue>,
+}
+impl Params {
+    fn number(&self, name: &str) -> f32 {
+        match self.values.get(name) {
+            Some(ParamValue::Number(value)) => *value,
+            _ => 0.0,
+        }
+    }
+
+    fn color(&self, name: &str) -> [u8; 3] {
+        match self.values.get(name) {
+            Some(ParamValue::Color(value)) => *value,
+            _ => [0, 0, 0],
+        }
+    }
+}
+
+fn number(name: &'static str, min: f32, max: f32, default: f32) -> ParamSpec {
+    ParamSpec{ name, kind: ParamKind::Number{ min, max, default } }
+}
+
+fn color(name: &'static str, default: [u8; 3]) -> ParamSpec {
+    ParamSpec{ name, kind: ParamKind::Color{ default } }
+}
+
+fn parse_param(spec: &ParamSpec, value: Option<&Value>) -> Result<ParamValue, String> {
+    match (&spec.kind, value) {
+        (ParamKind::Number{ default, .. }, None) => Ok(ParamValue::Number(*default)),
+        (ParamKind::Number{ min, max, .. }, Some(value)) => match value.as_f64() {
+            Some(value) if (*min..=*max).contains(&(value as f32)) => Ok(ParamValue::Number(value as f32)),
+            Some(value) => Err(format!("Parameter {} must be between {} and {}, got {}", spec.name, min, max, value)),
+            None => Err(format!("Parameter {} must be a number", spec.name)),
+        },
+        (ParamKind::Color{ default }, None) => Ok(ParamValue::Color(*default)),
+        (ParamKind::Color{ .. }, Some(value)) => match serde_json::from_value::<[u8; 3]>(value.clone()) {
+            Ok(rgb) => Ok(ParamValue::Color(rgb)),
+            Err(_) => Err(format!("Parameter {} must be an [r, g, b] color", spec.name)),
+        },
+    }
+}
+
+fn validate_params(info: &EffectInfo, params: &Value) -> Result<Params, String> {
+    let object = match params {
+        Value::Object(object) => object,
+        Value::Null => &serde_json::Map::new(),
+        _ => return Err(String::from("Effect parameters must be a JSON object")),
+    };
+
+    if let Some(name) = object.keys().find(|name| !info.params.iter().any(|spec| spec.name == name.as_str())) {
+        return Err(format!("Effect {} has no parameter {}", info.name, name));
+    }
+
+    let mut values = HashMap::new();
+    for spec in &info.params {
+        values.insert(spec.name, parse_param(spec, object.get(spec.name))?);
+    }
+
+    Ok(Params{ values })
+}
+
+fn scale_color(rgb: [u8; 3], level: f32) -> [u8; 3] {
+    rgb.map(|value| (value as f32 * level.clamp(0.0, 1.0)).round() as u8)
+}
+
+fn hash(x: i32, y: i32, z: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h = (h ^ (h >> 13)).wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+
+    h as f32 / u32::MAX as f32
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn value_noise(x: f32, y: f32, z: f32) -> f32 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let (fx, fy, fz) = (smooth(x - x0), smooth(y - y0), smooth(z - z0));
+    let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let plane = |z: i32| {
+        let top = lerp(hash(x0, y0, z), hash(x0 + 1, y0, z), fx);
+        let bottom = lerp(hash(x0, y0 + 1, z), hash(x0 + 1, y0 + 1, z), fx);
+        lerp(top, bottom, fy)
+    };
+
+    lerp(plane(z0), plane(z0 + 1), fz)
+}
+
+fn render_leds<F: Fn(f32, f32) -> [u8; 3]>(canvas: &Canvas, pixel: F) -> Frame {
+    let mut frame = Vec::with_capacity(canvas.positions.len() * 3);
+    for &(x, y) in &canvas.positions {
+        frame.extend_from_slice(&pixel(x as f32, y as f32));
+    }
+
+    frame
+}
+
+struct Rainbow {
+    speed: f32,
+    scale: f32,
+    saturation: f32,
+    value: f32,
+}
+impl Rainbow {
+    fn info() -> EffectInfo {
+        EffectInfo{
+            name: "rainbow",
+            description: "Hue gradient scrolling across the lamp",
+            params: vec![
+                number("speed", 0.0, 10.0, 0.2),
+                number("scale", 0.0, 10.0, 1.0),
+                number("saturation", 0.0, 1.0, 1.0),
+                number("value", 0.0, 1.0, 1.0),
+            ],
+        }
+    }
+
+    fn new(params: &Params) -> Self {
+        Rainbow{
+            speed: params.number("speed"),
+            scale: params.number("scale"),
+            saturation: params.number("saturation"),
+            value: params.number("value"),
+        }
+    }
+}
+impl Effect for Rainbow {
+    fn render(&self, canvas: &Canvas, elapsed: Duration) -> Frame {
+        let t = elapsed.as_secs_f32();
+        let width = canvas.width as f32;
+
+        render_leds(canvas, |x, _| {
+            let hue = 360.0 * (x / width * self.scale + t * self.speed);
+            color::hsv_to_rgb([hue, self.saturation, self.value])
+        })
+    }
+}
+
+struct Breathing {
+    color: [u8; 3],
+    period: f32,
+    min: f32,
+}
+impl Breathing {
+    fn info() -> EffectInfo {
+        EffectInfo{
+            name: "breathing",
+            description: "Single color slowly pulsing in brightness",
+            params: vec![
+                color("color", [255, 255, 255]),
+                number("period_s", 0.5, 60.0, 4.0),
+                number("min", 0.0, 1.0, 0.1),
+            ],
+        }
+    }
+
+    fn new(params: &Params) -> Self {
+        Breathing{
+            color: params.color("color"),
+            period: params.number("period_s"),
+            min: params.number("min"),
+        }
+    }
+}
+impl Effect for Breathing {
+    fn render(&self, canvas: &Canvas, elapsed: Duration) -> Frame {
+        let phase = elapsed.as_secs_f32() / self.period * TAU;
+        let level = self.min + (1.0 - self.min) * (0.5 - 0.5 * phase.cos());
+        let rgb = scale_color(self.color, level);
+
+        render_leds(canvas, |_, _| rgb)
+    }
+}
+
+struct ColorCycle {
+    speed: f32,
+    saturation: f32,
+    value: f32,
+}
+impl ColorCycle {
+    fn info() -> EffectInfo {
+        EffectInfo{
+            name: "color-cycle",
+            description: "Whole lamp cycling through the hue wheel",
+            params: vec![
+                number("speed", 0.0, 360.0, 30.0),
+                number("saturation", 0.0, 1.0, 1.0),
+                number("value", 0.0, 1.0, 1.0),
+            ],
+        }
+    }
+
+    fn new(params: &Params) -> Self {
+        ColorCycle{
+            speed: params.number("speed"),
+            saturation: params.number("saturation"),
+            value: params.number("value"),
+        }
+    }
+}
+impl Effect for ColorCycle {
+    fn render(&self, canvas: &Canvas, elapsed: Duration) -> Frame {
+        let hue = elapsed.as_secs_f32() * self.speed;
+        let rgb = color::hsv_to_rgb([hue, self.saturation, self.value]);
+
+        render_leds(canvas, |_, _| rgb)
+    }
+}
+
+struct Noise {
+    color: [u8; 3],
+    speed: f32,
+    scale: f32,
+}
+impl Noise {
+    fn info() -> EffectInfo {
+        EffectInfo{
+            name: "noise",
+            description: "Drifting value noise in a single color",
+            params: vec![
+                color("color", [255, 120, 20]),
+                number("speed", 0.0, 10.0, 1.0),
+                number("scale", 0.01, 2.0, 0.2),
+            ],
+        }
+    }
+
+    fn new(params: &Params) -> Self {
+        Noise{
+            color: params.color("color"),
+            speed: params.number("speed"),
+            scale: params.number("scale"),
+        }
+    }
+}
+impl Effect for Noise {
+    fn render(&self, canvas: &Canvas, elapsed: Duration) -> Frame {
+        let z = elapsed.as_secs_f32() * self.speed;
+
+        render_leds(canvas, |x, y| {
+            let level = value_noise(x * self.scale, y * self.scale, z);
+            scale_color(self.color, level)
+        })
+    }
+}
+
+struct Plasma {
+    speed: f32,
+    scale: f32,
+}
+impl Plasma {
+    fn info() -> EffectInfo {
+        EffectInfo{
+            name: "plasma",
+            description: "Classic demoscene plasma of overlapping sine waves",
+            params: vec![
+                number("speed", 0.0, 10.0, 1.0),
+                number("scale", 0.01, 2.0, 0.3),
+            ],
+        }
+    }
+
+    fn new(params: &Params) -> Self {
+        Plasma{
+            speed: params.number("speed"),
+            scale: params.number("scale"),
+        }
+    }
+}
+impl Effect for Plasma {
+    fn render(&self, canvas: &Canvas, elapsed: Duration) -> Frame {
+        let t = elapsed.as_secs_f32() * self.speed;
+        let cx = canvas.width as f32 / 2.0;
+        let cy = canvas.height as f32 / 2.0;
+
+        render_leds(canvas, |x, y| {
+            let (sx, sy) = (x * self.scale, y * self.scale);
+            let radial = ((x - cx) * (x - cx) + (y - cy) * (y - cy)).sqrt() * self.scale;
+            let v = (sx + t).sin() + (sy + t * 0.7).sin() + ((sx + sy) * 0.5 + t * 1.3).sin() + (radial - t).sin();
+
+            color::hsv_to_rgb([(v + 4.0) * 45.0, 1.0, 1.0])
+        })
+    }
+}
+
+pub fn effect_list() -> Vec<EffectInfo> {
+    vec![
+        Rainbow::info(),
+        Breathing::info(),
+        ColorCycle::info(),
+        Noise::info(),
+        Plasma::info(),
+    ]
+}
+
+pub fn create_effect(name: &str, params: &Value) -> Result<Box<dyn Effect>, String> {
+    let info = match effect_list().into_iter().find(|info| info.name == name) {
+        Some(info) => info,
+        None => return Err(format!("Unknown effect {}", name)),
+    };
+    let params = validate_params(&info, params)?;
+
+    match name {
+        "rainbow" => Ok(Box::new(Rainbow::new(&params))),
+        "breathing" => Ok(Box::new(Breathing::new(&params))),
+        "color-cycle" => Ok(Box::new(ColorCycle::new(&params))),
+        "noise" => Ok(Box::new(Noise::new(&params))),
+        "plasma" => Ok(Box::new(Plasma::new(&params))),
+        _ => Err(format!("Unknown effect {}", name)),
+    }
+}
@@ -1,19 +1,82 @@
-use image::{AnimationDecoder, Pixel};
+use image::Pixel;
 
-use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::imgops;
+use crate::adjust::Adjustments;
+use crate::imgops::{self, ResampleOptions};
+use crate::layout::PixelLayout;
+use crate::ledmap::LedMap;
+use crate::quantize::QuantizeOptions;
+
+pub const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(30);
+// Browsers play GIF frames with a delay of 10 ms or less at 100 ms, and
+// animations are authored against that behaviour.
+const MIN_GIF_FRAME_DURATION: Duration = Duration::from_millis(20);
+const SHORT_GIF_FRAME_DURATION: Duration = Duration::from_millis(100);
 
 pub type Frame = Vec<u8>;
-pub type Frames = Vec<Frame>;
 
+pub struct TimedFrame {
+    pub frame: Frame,
+    pub duration: Duration,
+}
+impl TimedFrame {
+    pub fn new(frame: Frame) -> Self {
+        TimedFrame{ frame, duration: DEFAULT_FRAME_DURATION }
+    }
+}
+
+pub type Frames = Vec<TimedFrame>;
+
+#[derive(Clone)]
 pub struct FrameSpec {
-    pub width: u8,
-    pub height: u8,
+    pub width: u16,
+    pub height: u16,
+    pub layout: PixelLayout,
+    pub led_map: Option<Arc<LedMap>>,
 }
 impl FrameSpec {
     pub fn len(&self) -> u32 {
-        self.width as u32 * self.height as u32
+        match &self.led_map {
+            Some(led_map) => led_map.len() as u32,
+            None => self.width as u32 * self.height as u32,
+        }
+    }
+
+    pub fn led_positions(&self) -> Vec<(u32, u32)> {
+        match &self.led_map {
+            Some(led_map) => led_map.positions.clone(),
+            None => self.layout.led_positions(self.width as u32, self.height as u32),
+        }
+    }
+}
+
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pub positions: Vec<(u32, u32)>,
+    grid: Vec<Option<usize>>,
+}
+impl Canvas {
+    pub fn new(frame_spec: &FrameSpec) -> Self {
+        let width = frame_spec.width as u32;
+        let height = frame_spec.height as u32;
+        let positions = frame_spec.led_positions();
+
+        let mut grid = vec![None; (width * height) as usize];
+        for (led, (x, y)) in positions.iter().enumerate() {
+            grid[(y * width + x) as usize] = Some(led);
+        }
+
+        Canvas{ width, height, positions, grid }
+    }
+
+    pub fn pixel(&self, frame: &Frame, x: u32, y: u32) -> [u8; 3] {
+        match self.grid[(y * self.width + x) as usize] {
+            Some(led) => [frame[led * 3], frame[led * 3 + 1], frame[led * 3 + 2]],
+            None => [0, 0, 0],
+        }
     }
 }
 
@@ -21,10 +84,10 @@ pub trait IntoFrameSpec {
     fn into_framespec(self) -> FrameSpec;
 }
 
-impl IntoFrameSpec for (u8, u8) {
+impl IntoFrameSpec for (u16, u16) {
     fn into_framespec(self) -> FrameSpec {
         let (width, height) = self;
-        FrameSpec{ width, height }
+        FrameSpec{ width, height, layout: PixelLayout::default(), led_map: None }
     }
 }
 
@@ -34,100 +97,73 @@ impl IntoFrameSpec for FrameSpec {
     }
 }
 
-fn image_data_to_frame(img: image::DynamicImage) -> Vec<u8> {
-    let mut frame_rows = Vec::new();
-
-    for (idx, row) in img.into_rgba8().rows().enumerate() {
-        let mut frame_row = Vec::new();
-
-        let is_reverse_row = idx % 2 == 0;
-
-        for pixel in row {
-            let a: f32 = pixel.alpha() as _;
-            let rgb = pixel.channels();
-            let mut r: f32 = rgb[0] as _;
-            let mut g: f32 = rgb[1] as _;
-            let mut b: f32 = rgb[2] as _;
-
-            r = r * a / 255.0;
-            g = g * a / 255.0;
-            b = b * a / 255.0;
-
-            if is_reverse_row {
-                frame_row.push(b as u8);
-                frame_row.push(g as u8);
-                frame_row.push(r as u8);
-            } else {
-                frame_row.push(r as u8);
-                frame_row.push(g as u8);
-                frame_row.push(b as u8);
-            }
-        }
+impl IntoFrameSpec for &FrameSpec {
+    fn into_framespec(self) -> FrameSpec {
+        self.clone()
+    }
+}
 
-        if is_reverse_row {
-            frame_row.reverse();
-        }
+fn image_data_to_frame(img: image::DynamicImage, positions: &[(u32, u32)]) -> Vec<u8> {
+    let img = img.into_rgba8();
+
+    let mut frame = Vec::with_capacity(positions.len() * 3);
+    for &(x, y) in positions {
+        let pixel = match img.get_pixel_checked(x, y) {
+            Some(pixel) => pixel,
+            None => {
+                frame.extend_from_slice(&[0, 0, 0]);
+                continue;
+            },
+        };
 
-        frame_rows.push(frame_row);
-    }
+        let a: f32 = pixel.alpha() as _;
+        let rgb = pixel.channels();
+        let mut r: f32 = rgb[0] as _;
+        let mut g: f32 = rgb[1] as _;
+        let mut b: f32 = rgb[2] as _;
+
+        r = r * a / 255.0;
+        g = g * a / 255.0;
+        b = b * a / 255.0;
 
-    let mut frame = Vec::new();
-    for frame_row in frame_rows.into_iter().rev() {
-        frame.extend(frame_row);
+        frame.push(r as u8);
+        frame.push(g as u8);
+        frame.push(b as u8);
     }
 
     frame
 }
 
-fn frames_from_gif_image(image_bytes: &[u8]) -> Result<Frames, String> {
-    let decoder = match image::codecs::gif::GifDecoder::new(Cursor::new(image_bytes)) {
-        Ok(decoder) => decoder,
-        Err(e) => return Err(format!("Failed to decode GIF: {}", e)),
-    };
-
+fn frames_from_animation(anim_frames: Vec<image::Frame>, img_format: image::ImageFormat, positions: &[(u32, u32)]) -> Frames {
     let mut frames = Vec::new();
-    let gif_frames = decoder.into_frames();
-    for gif_frame in gif_frames {
-        let img: image::DynamicImage = match gif_frame {
-            Ok(gif_frame) => gif_frame.into_buffer().into(),
-            Err(_) => continue,
-        };
+    for anim_frame in anim_frames {
+        let mut duration = Duration::from(anim_frame.delay());
+        if img_format == image::ImageFormat::Gif && duration < MIN_GIF_FRAME_DURATION {
+            duration = SHORT_GIF_FRAME_DURATION;
+        } else if duration.is_zero() {
+            duration = DEFAULT_FRAME_DURATION;
+        }
 
-        let frame = image_data_to_frame(img);
-        frames.push(frame)
+        let frame = image_data_to_frame(anim_frame.into_buffer().into(), positions);
+        frames.push(TimedFrame{ frame, duration })
     }
 
-    Ok(frames)
+    frames
 }
 
-fn frames_from_static_image(img: image::DynamicImage) -> Frames {
-    let frame = image_data_to_frame(img);
+fn frames_from_static_image(img: image::DynamicImage, positions: &[(u32, u32)]) -> Frames {
+    let frame = image_data_to_frame(img, positions);
 
-    vec![frame]
+    vec![TimedFrame::new(frame)]
 }
 
-pub fn frames_from_image<F: IntoFrameSpec>(frame_spec: F, image_bytes: &[u8]) -> Result<Frames, String> {
-    let resampled_image = imgops::resample_image(frame_spec, image_bytes)?;
-
-    let img = match image::ImageReader::new(Cursor::new(&resampled_image)).with_guessed_format() {
-        Ok(img) => img,
-        Err(e) => return Err(format!("Failed to determine image format: {}", e)),
-    };
-    let img_format = match img.format() {
-        Some(format) => format,
-        None => image::ImageFormat::Png,
-    };
-
-
-    let frames = if img_format == image::ImageFormat::Gif {
-        frames_from_gif_image(&resampled_image)?
-    } else {
-        let img = match img.decode() {
-            Ok(img) => img,
-            Err(e) => return Err(format!("Failed to decode image: {}", e)),
-        };
+pub fn frames_from_image<F: IntoFrameSpec>(frame_spec: F, image_bytes: &[u8], options: &ResampleOptions, adjust: &Adjustments, quantize: &QuantizeOptions) -> Result<Frames, String> {
+    let frame_spec = frame_spec.into_framespec();
+    let positions = frame_spec.led_positions();
 
-        frames_from_static_image(img)
+    let frames = match imgops::resample(&frame_spec, image_bytes, options, adjust, quantize)? {
+        imgops::Resampled::Animation(anim_frames, img_format) => frames_from_animation(anim_frames, img_format, &positions),
+        imgops::Resampled::Static(img) => frames_from_static_image(img, &positions),
     };
 
     Ok(frames)
@@ -136,13 +172,11 @@ pub fn frames_from_image<F: IntoFrameSpec>(frame_spec: F, image_bytes: &[u8]) ->
 pub fn frame_from_rgb<F: IntoFrameSpec>(frame_spec: F, r: u8, g: u8, b: u8) -> Vec<u8> {
     let frame_spec = frame_spec.into_framespec();
 
-    let mut frame = Vec::with_capacity(frame_spec.len() as _);
-    for _ in 0..frame_spec.width {
-        for _ in 0..frame_spec.height {
-            frame.push(r);
-            frame.push(g);
-            frame.push(b);
-        }
+    let mut frame = Vec::with_capacity(frame_spec.len() as usize * 3);
+    for _ in 0..frame_spec.len() {
+        frame.push(r);
+        frame.push(g);
+        frame.push(b);
     }
 
     frame
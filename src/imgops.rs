@@ -1,43 +1,298 @@
 use image::{self, GenericImageView};
 use image::{AnimationDecoder, ImageDecoder};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use std::path::Path;
 use std::io::Cursor;
 
+use crate::adjust::Adjustments;
 use crate::frame::{FrameSpec, IntoFrameSpec};
+use crate::quantize::{self, QuantizeOptions};
 
-fn resample_gif_frames(frame_spec: FrameSpec, frames: image::Frames) -> Vec<image::Frame> {
+const MAX_ZOOM: f32 = 64.0;
+
+type SourceRect = (u32, u32, u32, u32);
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    #[default]
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Lanczos,
+    Area,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FitMode {
+    #[default]
+    Stretch,
+    Contain,
+    Cover,
+    Center,
+}
+
+fn serialize_hex_color<S>(color: &[u8; 3], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer
+{
+    serializer.serialize_str(&format!("{:02x}{:02x}{:02x}", color[0], color[1], color[2]))
+}
+
+fn deserialize_hex_color<'de, D>(deserializer: D) -> Result<[u8; 3], D::Error>
+where
+    D: Deserializer<'de>
+{
+    let color = String::deserialize(deserializer)?;
+    let hex = color.trim_start_matches('#');
+
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]),
+        _ => Err(serde::de::Error::custom(format!("invalid color {:?}, expected six hex digits", color))),
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+pub struct ResampleOptions {
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub fit: FitMode,
+    #[serde(default, serialize_with = "serialize_hex_color", deserialize_with = "deserialize_hex_color")]
+    pub background: [u8; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop_x: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop_y: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop_w: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop_h: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_x: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_y: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zoom: Option<f32>,
+}
+impl ResampleOptions {
+    fn source_rect(&self, width: u32, height: u32) -> Result<Option<SourceRect>, String> {
+        let crop = [self.crop_x, self.crop_y, self.crop_w, self.crop_h];
+        let has_crop = crop.iter().any(|value| value.is_some());
+        let has_focus = self.focus_x.is_some() || self.focus_y.is_some() || self.zoom.is_some();
+
+        match (has_crop, has_focus) {
+            (false, false) => Ok(None),
+            (true, true) => Err(String::from("Use either a crop rectangle or a focus point with zoom, not both")),
+            (true, false) => {
+                let [x, y, w, h] = match crop {
+                    [Some(x), Some(y), Some(w), Some(h)] => [x, y, w, h],
+                    _ => return Err(String::from("Crop rectangle needs crop_x, crop_y, crop_w and crop_h")),
+                };
+
+                if w == 0 || h == 0 || x as u64 + w as u64 > width as u64 || y as u64 + h as u64 > height as u64 {
+                    return Err(format!("Crop rectangle {}x{} at ({}, {}) does not fit the {}x{} image", w, h, x, y, width, height));
+                }
+
+                Ok(Some((x, y, w, h)))
+            },
+            (false, true) => {
+                let zoom = self.zoom.unwrap_or(1.0);
+                if !(1.0..=MAX_ZOOM).contains(&zoom) {
+                    return Err(format!("Zoom must be between 1 and {}, got {}", MAX_ZOOM, zoom));
+                }
+
+                let focus_x = self.focus_x.unwrap_or(0.5);
+                let focus_y = self.focus_y.unwrap_or(0.5);
+                if !(0.0..=1.0).contains(&focus_x) || !(0.0..=1.0).contains(&focus_y) {
+                    return Err(String::from("Focus point coordinates must be between 0.0 and 1.0"));
+                }
+
+                let w = ((width as f32 / zoom).round() as u32).clamp(1, width);
+                let h = ((height as f32 / zoom).round() as u32).clamp(1, height);
+                let x = ((focus_x * width as f32 - w as f32 / 2.0).round().max(0.0) as u32).min(width - w);
+                let y = ((focus_y * height as f32 - h as f32 / 2.0).round().max(0.0) as u32).min(height - h);
+
+                Ok(Some((x, y, w, h)))
+            },
+        }
+    }
+}
+
+fn deserialize_optional_hex_color<'de, D>(deserializer: D) -> Result<Option<[u8; 3]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_hex_color(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+pub struct ResampleQuery {
+    pub filter: Option<Filter>,
+    pub fit: Option<FitMode>,
+    #[serde(default, deserialize_with = "deserialize_optional_hex_color")]
+    pub background: Option<[u8; 3]>,
+    pub crop_x: Option<u32>,
+    pub crop_y: Option<u32>,
+    pub crop_w: Option<u32>,
+    pub crop_h: Option<u32>,
+    pub focus_x: Option<f32>,
+    pub focus_y: Option<f32>,
+    pub zoom: Option<f32>,
+}
+impl ResampleQuery {
+    pub fn apply(&self, options: ResampleOptions) -> ResampleOptions {
+        ResampleOptions{
+            filter: self.filter.unwrap_or(options.filter),
+            fit: self.fit.unwrap_or(options.fit),
+            background: self.background.unwrap_or(options.background),
+            crop_x: self.crop_x.or(options.crop_x),
+            crop_y: self.crop_y.or(options.crop_y),
+            crop_w: self.crop_w.or(options.crop_w),
+            crop_h: self.crop_h.or(options.crop_h),
+            focus_x: self.focus_x.or(options.focus_x),
+            focus_y: self.focus_y.or(options.focus_y),
+            zoom: self.zoom.or(options.zoom),
+        }
+    }
+}
+
+fn resize(img: &image::RgbaImage, width: u32, height: u32, filter: Filter) -> image::RgbaImage {
+    let filter = match filter {
+        Filter::Nearest => image::imageops::Nearest,
+        Filter::Triangle => image::imageops::Triangle,
+        Filter::CatmullRom => image::imageops::CatmullRom,
+        Filter::Lanczos => image::imageops::Lanczos3,
+        Filter::Area => return image::imageops::thumbnail(img, width, height),
+    };
+
+    image::imageops::resize(img, width, height, filter)
+}
+
+fn fit_image(img: &image::RgbaImage, frame_spec: &FrameSpec, options: &ResampleOptions) -> image::RgbaImage {
+    let (w, h) = img.dimensions();
+    let (tw, th) = (frame_spec.width as u32, frame_spec.height as u32);
+
+    let scale = match options.fit {
+        FitMode::Stretch => return resize(img, tw, th, options.filter),
+        FitMode::Contain => (tw as f32 / w as f32).min(th as f32 / h as f32),
+        FitMode::Cover => (tw as f32 / w as f32).max(th as f32 / h as f32),
+        FitMode::Center => 1.0,
+    };
+
+    let sw = ((w as f32 * scale).round() as u32).max(1);
+    let sh = ((h as f32 * scale).round() as u32).max(1);
+    let scaled = if (sw, sh) == (w, h) {
+        img.clone()
+    } else {
+        resize(img, sw, sh, options.filter)
+    };
+
+    let [r, g, b] = options.background;
+    let mut canvas = image::RgbaImage::from_pixel(tw, th, image::Rgba([r, g, b, 255]));
+    let x = (tw as i64 - sw as i64) / 2;
+    let y = (th as i64 - sh as i64) / 2;
+    image::imageops::overlay(&mut canvas, &scaled, x, y);
+
+    canvas
+}
+
+fn crop_and_fit(img: &image::RgbaImage, rect: Option<SourceRect>, frame_spec: &FrameSpec, options: &ResampleOptions) -> image::RgbaImage {
+    match rect {
+        Some((x, y, w, h)) => fit_image(&image::imageops::crop_imm(img, x, y, w, h).to_image(), frame_spec, options),
+        None => fit_image(img, frame_spec, options),
+    }
+}
+
+fn resample_animation_frames(frame_spec: FrameSpec, frames: image::Frames, rect: Option<SourceRect>, options: &ResampleOptions) -> Vec<image::Frame> {
     let mut resampled_frames = Vec::new();
     for frame in frames {
-        let img: image::DynamicImage = match frame {
-            Ok(frame) => frame.into_buffer().into(),
+        let frame = match frame {
+            Ok(frame) => frame,
             Err(_) => continue,
         };
+        let delay = frame.delay();
 
-        let resized_img: image::DynamicImage = image::imageops::resize(&img, frame_spec.width as _, frame_spec.height as _, image::imageops::Nearest).into();
-        let resized_frame = image::Frame::new(resized_img.into_rgba8());
+        let resized_img = crop_and_fit(frame.buffer(), rect, &frame_spec, options);
+        let resized_frame = image::Frame::from_parts(resized_img, 0, 0, delay);
         resampled_frames.push(resized_frame);
     }
 
     resampled_frames
 }
 
-fn resample_gif_image(frame_spec: FrameSpec, bytes: &[u8]) -> Result<Vec<u8>, String> {
-    let decoder = match image::codecs::gif::GifDecoder::new(Cursor::new(bytes)) {
-        Ok(decoder) => decoder,
-        Err(e) => return Err(format!("Failed to decode GIF: {}", e)),
-    };
+fn adjust_animation_frames(frames: Vec<image::Frame>, adjust: &Adjustments) -> Vec<image::Frame> {
+    frames.into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let mut buffer = frame.into_buffer();
+            adjust.apply(&mut buffer);
 
-    let (w, h) = decoder.dimensions();
-    let frames = decoder.into_frames();
+            image::Frame::from_parts(buffer, 0, 0, delay)
+        })
+        .collect()
+}
 
-    let resampled_frames = if w == frame_spec.width as u32 && h == frame_spec.height as u32 {
+fn quantize_animation_frames(frames: Vec<image::Frame>, quantize: &QuantizeOptions) -> Vec<image::Frame> {
+    let delays: Vec<image::Delay> = frames.iter().map(|frame| frame.delay()).collect();
+    let mut buffers: Vec<image::RgbaImage> = frames.into_iter().map(|frame| frame.into_buffer()).collect();
+    quantize::quantize_images(&mut buffers, quantize);
+
+    buffers.into_iter()
+        .zip(delays)
+        .map(|(buffer, delay)| image::Frame::from_parts(buffer, 0, 0, delay))
+        .collect()
+}
+
+fn resample_animated_image(frame_spec: FrameSpec, animation: Animation, options: &ResampleOptions, adjust: &Adjustments, quantize: &QuantizeOptions) -> Result<Vec<image::Frame>, String> {
+    let Animation{ width: w, height: h, frames } = animation;
+    let rect = options.source_rect(w, h)?;
+
+    let resampled_frames = if w == frame_spec.width as u32 && h == frame_spec.height as u32 && rect.is_none() {
         match frames.collect_frames() {
             Ok(frames) => frames,
-            Err(e) => return Err(format!("Failed to extract GIF frames: {}", e)),
+            Err(e) => return Err(format!("Failed to extract animation frames: {}", e)),
         }
     } else {
-        resample_gif_frames(frame_spec, frames)
+        resample_animation_frames(frame_spec, frames, rect, options)
+    };
+    let resampled_frames = if adjust.is_identity() {
+        resampled_frames
+    } else {
+        adjust_animation_frames(resampled_frames, adjust)
+    };
+    let resampled_frames = if quantize.is_none() {
+        resampled_frames
+    } else {
+        quantize_animation_frames(resampled_frames, quantize)
+    };
+
+    Ok(resampled_frames)
+}
+
+// GIF stores delays in whole centiseconds and players stretch anything below
+// 20 ms, so delays from other formats are rounded to survive the trip.
+fn gif_delay(delay: image::Delay) -> image::Delay {
+    let (numer, denom) = delay.numer_denom_ms();
+    let centis = ((numer as f64 / denom as f64) / 10.0).round().max(2.0) as u32;
+
+    image::Delay::from_numer_denom_ms(centis * 10, 1)
+}
+
+// Resampled animations are stored as GIF whatever their source format, since
+// that is the only animated format the encoder supports.
+fn encode_animation(frames: Vec<image::Frame>, img_format: image::ImageFormat) -> Result<Vec<u8>, String> {
+    let frames: Vec<image::Frame> = if img_format == image::ImageFormat::Gif {
+        frames
+    } else {
+        frames.into_iter()
+            .map(|frame| {
+                let delay = gif_delay(frame.delay());
+                image::Frame::from_parts(frame.into_buffer(), 0, 0, delay)
+            })
+            .collect()
     };
 
     let mut reencoded_img = Vec::new();
@@ -45,7 +300,7 @@ fn resample_gif_image(frame_spec: FrameSpec, bytes: &[u8]) -> Result<Vec<u8>, St
         let mut encoder = image::codecs::gif::GifEncoder::new(Cursor::new(&mut reencoded_img));
         encoder.set_repeat(image::codecs::gif::Repeat::Infinite).unwrap();
 
-        match encoder.encode_frames(resampled_frames) {
+        match encoder.encode_frames(frames) {
             Ok(()) => (),
             Err(e) => return Err(format!("Failed to encode GIF frames: {}", e)),
         }
@@ -54,30 +309,102 @@ fn resample_gif_image(frame_spec: FrameSpec, bytes: &[u8]) -> Result<Vec<u8>, St
     Ok(reencoded_img)
 }
 
-fn resample_static_image(frame_spec: FrameSpec, img: image::DynamicImage, img_format: image::ImageFormat) -> Result<Vec<u8>, String> {
+fn resample_static_image(frame_spec: FrameSpec, img: image::DynamicImage, options: &ResampleOptions, adjust: &Adjustments, quantize: &QuantizeOptions) -> Result<image::DynamicImage, String> {
     let (w, h) = img.dimensions();
+    let rect = options.source_rect(w, h)?;
+    let fits = w == frame_spec.width as u32 && h == frame_spec.height as u32 && rect.is_none();
 
-    let resampled_img: image::DynamicImage = if w == frame_spec.width as u32 && h == frame_spec.height as u32 {
-        img
+    if fits && adjust.is_identity() && quantize.is_none() {
+        Ok(img)
     } else {
-        image::imageops::resize(&img, frame_spec.width as _, frame_spec.height as _, image::imageops::Nearest).into()
-    };
+        let has_alpha = img.color().has_alpha();
+        let mut fitted = if fits {
+            img.into_rgba8()
+        } else {
+            crop_and_fit(&img.into_rgba8(), rect, &frame_spec, options)
+        };
+        adjust.apply(&mut fitted);
+        quantize::quantize_images(std::slice::from_mut(&mut fitted), quantize);
+        let fitted: image::DynamicImage = fitted.into();
 
+        if has_alpha {
+            Ok(fitted)
+        } else {
+            Ok(fitted.into_rgb8().into())
+        }
+    }
+}
+
+// Always PNG, so the pixels match what is sent to the device instead of
+// going through a lossy re-encode in the upload's own format.
+fn encode_static_image(img: image::DynamicImage) -> Result<Vec<u8>, String> {
     let mut reencoded_img = Vec::new();
-    match resampled_img.write_to(Cursor::new(&mut reencoded_img), img_format) {
+    match img.write_to(Cursor::new(&mut reencoded_img), image::ImageFormat::Png) {
         Ok(_) => Ok(reencoded_img),
         Err(e) => Err(format!("Failed to encode image: {}", e)),
     }
 }
 
-pub fn is_image(file_path: &Path) -> bool {
-    match image::ImageReader::open(file_path) {
-        Ok(_) => true,
-        Err(_) => false,
+pub struct Animation<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub frames: image::Frames<'a>,
+}
+
+pub fn decode_animation(bytes: &[u8], img_format: image::ImageFormat) -> Result<Option<Animation<'_>>, String> {
+    match img_format {
+        image::ImageFormat::Gif => {
+            let decoder = match image::codecs::gif::GifDecoder::new(Cursor::new(bytes)) {
+                Ok(decoder) => decoder,
+                Err(e) => return Err(format!("Failed to decode GIF: {}", e)),
+            };
+            let (width, height) = decoder.dimensions();
+
+            Ok(Some(Animation{ width, height, frames: decoder.into_frames() }))
+        },
+        image::ImageFormat::Png => {
+            let decoder = match image::codecs::png::PngDecoder::new(Cursor::new(bytes)) {
+                Ok(decoder) => decoder,
+                Err(e) => return Err(format!("Failed to decode PNG: {}", e)),
+            };
+            match decoder.is_apng() {
+                Ok(true) => (),
+                Ok(false) => return Ok(None),
+                Err(e) => return Err(format!("Failed to decode PNG: {}", e)),
+            }
+            let (width, height) = decoder.dimensions();
+
+            match decoder.apng() {
+                Ok(decoder) => Ok(Some(Animation{ width, height, frames: decoder.into_frames() })),
+                Err(e) => Err(format!("Failed to decode APNG: {}", e)),
+            }
+        },
+        image::ImageFormat::WebP => {
+            let decoder = match image::codecs::webp::WebPDecoder::new(Cursor::new(bytes)) {
+                Ok(decoder) => decoder,
+                Err(e) => return Err(format!("Failed to decode WebP: {}", e)),
+            };
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            let (width, height) = decoder.dimensions();
+
+            Ok(Some(Animation{ width, height, frames: decoder.into_frames() }))
+        },
+        _ => Ok(None),
     }
 }
 
-pub fn resample_image<F: IntoFrameSpec>(frame_spec: F, bytes: &[u8]) -> Result<Vec<u8>, String> {
+pub fn is_image(file_path: &Path) -> bool {
+    image::ImageReader::open(file_path).is_ok()
+}
+
+pub enum Resampled {
+    Animation(Vec<image::Frame>, image::ImageFormat),
+    Static(image::DynamicImage),
+}
+
+pub fn resample<F: IntoFrameSpec>(frame_spec: F, bytes: &[u8], options: &ResampleOptions, adjust: &Adjustments, quantize: &QuantizeOptions) -> Result<Resampled, String> {
     let frame_spec = frame_spec.into_framespec();
 
     let img = match image::ImageReader::new(Cursor::new(bytes)).with_guessed_format() {
@@ -89,14 +416,26 @@ pub fn resample_image<F: IntoFrameSpec>(frame_spec: F, bytes: &[u8]) -> Result<V
         None => image::ImageFormat::Png,
     };
 
-    if img_format == image::ImageFormat::Gif {
-        resample_gif_image(frame_spec, bytes)
+    if let Some(animation) = decode_animation(bytes, img_format)? {
+        let frames = resample_animated_image(frame_spec, animation, options, adjust, quantize)?;
+        Ok(Resampled::Animation(frames, img_format))
     } else {
         let img = match img.decode() {
             Ok(img) => img,
             Err(e) => return Err(format!("Failed to decode image: {}", e)),
         };
 
-        resample_static_image(frame_spec, img, img_format)
+        let img = resample_static_image(frame_spec, img, options, adjust, quantize)?;
+        Ok(Resampled::Static(img))
+    }
+}
+
+// Static images come back exactly as the device gets them. Animations are
+// only approximate, since the GIF encoder limits each frame to 256 colors and
+// rounds delays to centiseconds.
+pub fn resample_image<F: IntoFrameSpec>(frame_spec: F, bytes: &[u8], options: &ResampleOptions, adjust: &Adjustments, quantize: &QuantizeOptions) -> Result<Vec<u8>, String> {
+    match resample(frame_spec, bytes, options, adjust, quantize)? {
+        Resampled::Animation(frames, img_format) => encode_animation(frames, img_format),
+        Resampled::Static(img) => encode_static_image(img),
     }
 }
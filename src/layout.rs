@@ -0,0 +1,206 @@
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    #[default]
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    #[default]
+    Rows,
+    Columns,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorOrder {
+    #[default]
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+impl ColorOrder {
+    fn channels(&self) -> [usize; 3] {
+        match self {
+            ColorOrder::Rgb => [0, 1, 2],
+            ColorOrder::Rbg => [0, 2, 1],
+            ColorOrder::Grb => [1, 0, 2],
+            ColorOrder::Gbr => [1, 2, 0],
+            ColorOrder::Brg => [2, 0, 1],
+            ColorOrder::Bgr => [2, 1, 0],
+        }
+    }
+}
+
+fn default_serpentine() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct PixelLayout {
+    #[serde(default)]
+    pub origin: Corner,
+    #[serde(default)]
+    pub order: Order,
+    #[serde(default = "default_serpentine")]
+    pub serpentine: bool,
+    #[serde(default)]
+    pub rotation: u16,
+    #[serde(default)]
+    pub mirror_x: bool,
+    #[serde(default)]
+    pub mirror_y: bool,
+    #[serde(default)]
+    pub color_order: ColorOrder,
+}
+impl PixelLayout {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.rotation {
+            0 | 90 | 180 | 270 => Ok(()),
+            rotation => Err(format!("Rotation must be 0, 90, 180 or 270 degrees, got {}", rotation)),
+        }
+    }
+
+    fn physical_dims(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.rotation {
+            90 | 270 => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    fn physical_position(&self, led: u32, pw: u32, ph: u32) -> (u32, u32) {
+        let strip_len = match self.order {
+            Order::Rows => pw,
+            Order::Columns => ph,
+        };
+
+        let strip = led / strip_len;
+        let mut pos = led % strip_len;
+        if self.serpentine && strip % 2 == 1 {
+            pos = strip_len - 1 - pos;
+        }
+
+        let (mut x, mut y) = match self.order {
+            Order::Rows => (pos, strip),
+            Order::Columns => (strip, pos),
+        };
+
+        if matches!(self.origin, Corner::TopRight | Corner::BottomRight) {
+            x = pw - 1 - x;
+        }
+        if matches!(self.origin, Corner::BottomLeft | Corner::BottomRight) {
+            y = ph - 1 - y;
+        }
+        if self.mirror_x {
+            x = pw - 1 - x;
+        }
+        if self.mirror_y {
+            y = ph - 1 - y;
+        }
+
+        (x, y)
+    }
+
+    fn canvas_position(&self, px: u32, py: u32, width: u32, height: u32) -> (u32, u32) {
+        match self.rotation {
+            90 => (py, height - 1 - px),
+            180 => (width - 1 - px, height - 1 - py),
+            270 => (width - 1 - py, px),
+            _ => (px, py),
+        }
+    }
+
+    pub fn led_positions(&self, width: u32, height: u32) -> Vec<(u32, u32)> {
+        let (pw, ph) = self.physical_dims(width, height);
+
+        (0..width * height)
+            .map(|led| {
+                let (px, py) = self.physical_position(led, pw, ph);
+                self.canvas_position(px, py, width, height)
+            })
+            .collect()
+    }
+
+    pub fn apply_color_order(&self, frame: &mut [u8]) {
+        if self.color_order == ColorOrder::Rgb {
+            return;
+        }
+
+        let [c0, c1, c2] = self.color_order.channels();
+        for pixel in frame.chunks_exact_mut(3) {
+            let rgb = [pixel[0], pixel[1], pixel[2]];
+            pixel[0] = rgb[c0];
+            pixel[1] = rgb[c1];
+            pixel[2] = rgb[c2];
+        }
+    }
+}
+impl std::default::Default for PixelLayout {
+    fn default() -> Self {
+        PixelLayout{
+            origin: Corner::default(),
+            order: Order::default(),
+            serpentine: default_serpentine(),
+            rotation: 0,
+            mirror_x: false,
+            mirror_y: false,
+            color_order: ColorOrder::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The wiring hard-coded before layouts were configurable: rows from the
+    // bottom up, every other row reversed by flipping its BGR bytes.
+    fn baseline_frame(width: u32, height: u32) -> Vec<u8> {
+        let mut frame_rows = Vec::new();
+
+        for y in 0..height {
+            let is_reverse_row = y % 2 == 0;
+
+            let mut frame_row = Vec::new();
+            for x in 0..width {
+                let rgb = [x as u8, y as u8, 7];
+                if is_reverse_row {
+                    frame_row.extend([rgb[2], rgb[1], rgb[0]]);
+                } else {
+                    frame_row.extend(rgb);
+                }
+            }
+
+            if is_reverse_row {
+                frame_row.reverse();
+            }
+
+            frame_rows.push(frame_row);
+        }
+
+        frame_rows.into_iter().rev().flatten().collect()
+    }
+
+    #[test]
+    fn default_layout_matches_baseline_wiring() {
+        let layout = PixelLayout::default();
+
+        let mut frame = Vec::new();
+        for (x, y) in layout.led_positions(30, 32) {
+            frame.extend([x as u8, y as u8, 7]);
+        }
+        layout.apply_color_order(&mut frame);
+
+        assert_eq!(frame, baseline_frame(30, 32));
+    }
+}
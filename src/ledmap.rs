@@ -0,0 +1,84 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+pub struct LedMap {
+    pub positions: Vec<(u32, u32)>,
+}
+impl LedMap {
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+}
+
+fn parse_json_map(content: &str) -> Result<Vec<(Option<usize>, u32, u32)>, String> {
+    let positions: Vec<[u32; 2]> = match serde_json::from_str(content) {
+        Ok(positions) => positions,
+        Err(e) => return Err(format!("Cannot parse LED map: {}", e)),
+    };
+
+    Ok(positions.into_iter().map(|[x, y]| (None, x, y)).collect())
+}
+
+fn parse_csv_map(content: &str) -> Result<Vec<(Option<usize>, u32, u32)>, String> {
+    let mut positions = Vec::new();
+
+    for (line_no, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        let numbers: Result<Vec<u32>, _> = fields.iter().map(|field| field.parse::<u32>()).collect();
+        let numbers = match numbers {
+            Ok(numbers) => numbers,
+            Err(_) if positions.is_empty() && line_no == 0 => continue,
+            Err(_) => return Err(format!("Invalid LED map line {}: {}", line_no + 1, line)),
+        };
+
+        match numbers[..] {
+            [x, y] => positions.push((None, x, y)),
+            [led, x, y] => positions.push((Some(led as usize), x, y)),
+            _ => return Err(format!("LED map line {} needs x,y or index,x,y", line_no + 1)),
+        }
+    }
+
+    Ok(positions)
+}
+
+pub fn read_led_map(path: &Path, width: u16, height: u16) -> Result<LedMap, String> {
+    let content = match read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => return Err(format!("Cannot read LED map {}: {}", path.display(), e)),
+    };
+
+    let entries = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => parse_json_map(&content)?,
+        _ => parse_csv_map(&content)?,
+    };
+
+    let mut positions = vec![None; entries.len()];
+    for (idx, (led, x, y)) in entries.into_iter().enumerate() {
+        let led = led.unwrap_or(idx);
+
+        if x >= width as u32 || y >= height as u32 {
+            return Err(format!("LED {} at ({}, {}) lies outside the {}x{} canvas", led, x, y, width, height));
+        }
+
+        match positions.get_mut(led) {
+            Some(Some(_)) => return Err(format!("LED {} is listed more than once", led)),
+            Some(position) => *position = Some((x, y)),
+            None => return Err(format!("LED index {} is beyond the {} listed LEDs", led, positions.len())),
+        }
+    }
+
+    if positions.is_empty() {
+        return Err(String::from("LED map does not list any LEDs"));
+    }
+
+    let positions: Option<Vec<(u32, u32)>> = positions.into_iter().collect();
+    match positions {
+        Some(positions) => Ok(LedMap{ positions }),
+        None => Err(String::from("LED map has gaps in its LED indices")),
+    }
+}
@@ -1,36 +1,59 @@
+mod adjust;
+mod brightness;
+mod calibration;
+mod color;
 mod config;
 mod device;
+mod dither;
+mod effect;
 mod frame;
 mod imgops;
+mod layout;
+mod ledmap;
+mod pipeline;
+mod playback;
+mod player;
+mod power;
+mod protocol;
+mod quantize;
+mod sink;
 mod solid;
+mod state;
 mod templates;
+mod transition;
 
 use axum::{self, RequestExt};
 use axum::body::{Body, Bytes};
-use axum::extract::{Json, OptionalFromRequestParts, Path, Request, State};
+use axum::extract::{Json, OptionalFromRequestParts, Path, Query, Request, State};
 use axum::http;
 use axum::response::{IntoResponse, Response};
 use tower_http::trace::TraceLayer;
-use tracing::{info, error};
+use tracing::{info, error, warn};
 use tracing_subscriber::{fmt, EnvFilter};
 
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-const FRAME_COLS: u8 = 30;
-const FRAME_ROWS: u8 = 32;
-const FRAME_DIMS: (u8, u8) = (FRAME_COLS, FRAME_ROWS);
-const MILLIS_PER_FRAME: u64 = 30;
+use frame::IntoFrameSpec;
+use playback::PlaybackSource;
+use player::{FramesCmd, PlayerCmd};
+use transition::TransitionOptions;
 
-enum FramesCmd {
-    Empty,
-    Loop(frame::Frames),
-    Transition(frame::Frames),
-}
+const DEFAULT_FRAME_DIMS: (u16, u16) = (30, 32);
 
 #[derive(Clone)]
 struct AppState {
-    frames_tx: tokio::sync::mpsc::Sender<FramesCmd>,
+    device_status: device::SharedDeviceStatus,
+    device_ports: Option<device::SharedPortWatch>,
+    frame_spec: frame::FrameSpec,
+    player_tx: std::sync::mpsc::Sender<PlayerCmd>,
+    playback_stats: player::SharedPlaybackStats,
+    playback_status: player::SharedPlaybackStatus,
+    power_status: power::SharedPowerStatus,
+    recorded_frames: Option<sink::MemoryFrames>,
+    saved_state: Arc<Mutex<state::SavedState>>,
+    state_path: PathBuf,
     templates: PathBuf,
 }
 
@@ -85,17 +108,259 @@ fn serve_html_file(path: &str) -> Response<Body> {
     }
 }
 
+fn save_content(state: &AppState, content: state::SavedContent) {
+    let mut saved_state = state.saved_state.lock().unwrap();
+    saved_state.content = Some(content);
+
+    if let Err(e) = state::write_state(&state.state_path, &saved_state) {
+        error!("{}", e);
+    }
+}
+
+fn save_image_content(state: &AppState, image_bytes: &[u8], content: state::SavedContent) {
+    let mut saved_state = state.saved_state.lock().unwrap();
+    if let Err(e) = state::write_image(&state.state_path, image_bytes) {
+        error!("{}", e);
+        return;
+    }
+
+    saved_state.content = Some(content);
+    if let Err(e) = state::write_state(&state.state_path, &saved_state) {
+        error!("{}", e);
+    }
+}
+
+fn restore_content(state: &AppState) -> Result<(), String> {
+    let content = match state.saved_state.lock().unwrap().content.clone() {
+        Some(content) => content,
+        None => return Ok(()),
+    };
+
+    let cmd = match content {
+        state::SavedContent::Effect{ name, params } => {
+            let effect = effect::create_effect(&name, &params)?;
+            PlayerCmd::Frames(FramesCmd::Effect(effect), PlaybackSource::Effect{ name }, TransitionOptions::default())
+        },
+        state::SavedContent::Image{ playback, resample, adjust, quantize } => {
+            let playback = state::valid_or_default(playback, playback::PlaybackOptions::validate);
+            let adjust = state::valid_or_default(adjust, adjust::Adjustments::validate);
+            let quantize = state::valid_or_default(quantize, quantize::QuantizeOptions::validate);
+            let image_bytes = state::read_image(&state.state_path)?;
+            let frames = frame::frames_from_image(&state.frame_spec, &image_bytes, &resample, &adjust, &quantize)?;
+            PlayerCmd::Frames(FramesCmd::Play(frames, playback), PlaybackSource::Image, TransitionOptions::default())
+        },
+        state::SavedContent::SolidColor{ color: [r, g, b] } => {
+            let frame = solid::make_frame(&state.frame_spec, r, g, b);
+            PlayerCmd::Frames(FramesCmd::Transition(vec![frame::TimedFrame::new(frame)]), PlaybackSource::SolidColor, TransitionOptions::default())
+        },
+        state::SavedContent::Template{ name, playback } => {
+            let playback = state::valid_or_default(playback, playback::PlaybackOptions::validate);
+            let template_bytes = templates::read_template(&state.templates, name.clone())?;
+            let frames = frame::frames_from_image(&state.frame_spec, &template_bytes, &imgops::ResampleOptions::default(), &adjust::Adjustments::default(), &quantize::QuantizeOptions::default())?;
+            PlayerCmd::Frames(FramesCmd::Play(frames, playback), PlaybackSource::Template{ name }, TransitionOptions::default())
+        },
+    };
+
+    match state.player_tx.send(cmd) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to push frames to device queue: {}", e)),
+    }
+}
+
+async fn route_effect_list() -> Response<Body> {
+    let json = serde_json::to_string(&effect::effect_list()).unwrap();
+
+    respond_json(json).into_response()
+}
+
+async fn route_effect_start(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(transition): Query<TransitionOptions>,
+    Json(params): Json<serde_json::Value>
+) -> Response<Body> {
+    if let Err(e) = transition.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let effect = match effect::create_effect(&name, &params) {
+        Ok(effect) => effect,
+        Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let source = PlaybackSource::Effect{ name: name.clone() };
+    match state.player_tx.send(PlayerCmd::Frames(FramesCmd::Effect(effect), source, transition)) {
+        Ok(()) => {
+            save_content(&state, state::SavedContent::Effect{ name, params });
+            respond_ok().into_response()
+        },
+        Err(e) => {
+            error!("Failed to push effect to device queue: {}", e);
+            respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to push effect to device queue: {}", e)).into_response()
+        },
+    }
+}
+
 async fn route_index() -> Response<Body> {
     serve_html_file("web/index.html")
 }
 
-async fn route_resample(request: Request) -> Response<Body> {
+async fn route_device_status(
+    State(state): State<AppState>
+) -> Response<Body> {
+    let status = state.device_status.lock().unwrap().clone();
+    let json = serde_json::to_string(&status).unwrap();
+
+    respond_json(json).into_response()
+}
+
+async fn route_brightness(
+    State(state): State<AppState>
+) -> Response<Body> {
+    let brightness = state.saved_state.lock().unwrap().brightness;
+    let json = serde_json::to_string(&brightness::BrightnessResponse{ brightness }).unwrap();
+
+    respond_json(json).into_response()
+}
+
+async fn route_brightness_set(
+    State(state): State<AppState>,
+    Json(request): Json<brightness::BrightnessRequest>
+) -> Response<Body> {
+    if !(0.0..=1.0).contains(&request.brightness) {
+        return respond_error(http::StatusCode::BAD_REQUEST, String::from("Brightness must be between 0.0 and 1.0")).into_response();
+    }
+
+    let fade = Duration::from_millis(request.fade_ms);
+    if let Err(e) = state.player_tx.send(PlayerCmd::Brightness(request.brightness, fade)) {
+        error!("Failed to push brightness to device queue: {}", e);
+        return respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to push brightness to device queue: {}", e)).into_response();
+    }
+
+    let mut saved_state = state.saved_state.lock().unwrap();
+    saved_state.brightness = request.brightness;
+    if let Err(e) = state::write_state(&state.state_path, &saved_state) {
+        error!("{}", e);
+        return respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+
+    respond_ok().into_response()
+}
+
+async fn route_device_ports(
+    State(state): State<AppState>
+) -> Response<Body> {
+    let ports = match &state.device_ports {
+        Some(ports) => ports.lock().unwrap().clone(),
+        None => device::scan_ports(None),
+    };
+    let json = serde_json::to_string(&ports).unwrap();
+
+    respond_json(json).into_response()
+}
+
+async fn route_output_frames(
+    State(state): State<AppState>
+) -> Response<Body> {
+    let recorded_frames = match &state.recorded_frames {
+        Some(recorded_frames) => recorded_frames,
+        None => return respond_error(http::StatusCode::NOT_FOUND, String::from("Output is not recording to memory")).into_response(),
+    };
+
+    let payload = recorded_frames.lock().unwrap().iter().flatten().copied().collect();
+
+    respond_binary(payload).into_response()
+}
+
+async fn route_output_stats(
+    State(state): State<AppState>
+) -> Response<Body> {
+    let stats = state.playback_stats.lock().unwrap().clone();
+    let json = serde_json::to_string(&stats).unwrap();
+
+    respond_json(json).into_response()
+}
+
+async fn route_playback_status(
+    State(state): State<AppState>
+) -> Response<Body> {
+    let status = state.playback_status.lock().unwrap().clone();
+    let json = serde_json::to_string(&status).unwrap();
+
+    respond_json(json).into_response()
+}
+
+fn send_playback_cmd(state: &AppState, cmd: PlayerCmd) -> Response<Body> {
+    match state.player_tx.send(cmd) {
+        Ok(()) => respond_ok().into_response(),
+        Err(e) => {
+            error!("Failed to push playback command to device queue: {}", e);
+            respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to push playback command to device queue: {}", e)).into_response()
+        },
+    }
+}
+
+async fn route_playback_pause(
+    State(state): State<AppState>
+) -> Response<Body> {
+    send_playback_cmd(&state, PlayerCmd::Pause)
+}
+
+async fn route_playback_resume(
+    State(state): State<AppState>
+) -> Response<Body> {
+    send_playback_cmd(&state, PlayerCmd::Resume)
+}
+
+async fn route_playback_seek(
+    State(state): State<AppState>,
+    Path(frame_index): Path<usize>
+) -> Response<Body> {
+    let frame_count = state.playback_status.lock().unwrap().frame_count;
+    if frame_index >= frame_count {
+        return respond_error(http::StatusCode::BAD_REQUEST, format!("Frame {} is out of range, content has {} frames", frame_index, frame_count)).into_response();
+    }
+
+    send_playback_cmd(&state, PlayerCmd::Seek(frame_index))
+}
+
+async fn route_playback_step(
+    State(state): State<AppState>,
+    Path(frames): Path<i64>
+) -> Response<Body> {
+    send_playback_cmd(&state, PlayerCmd::Step(frames))
+}
+
+async fn route_power(
+    State(state): State<AppState>
+) -> Response<Body> {
+    let status = state.power_status.lock().unwrap().clone();
+    let json = serde_json::to_string(&status).unwrap();
+
+    respond_json(json).into_response()
+}
+
+async fn route_resample(
+    State(state): State<AppState>,
+    Query(resample): Query<imgops::ResampleOptions>,
+    Query(adjust): Query<adjust::Adjustments>,
+    Query(quantize): Query<quantize::QuantizeOptions>,
+    request: Request
+) -> Response<Body> {
+    if let Err(e) = adjust.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    if let Err(e) = quantize.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
     let body = match request.extract::<Bytes, _>().await {
         Ok(body) => body,
         Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     };
 
-    match imgops::resample_image(FRAME_DIMS, &body) {
+    match imgops::resample_image(&state.frame_spec, &body, &resample, &adjust, &quantize) {
         Ok(resampled_image) => respond_binary(resampled_image).into_response(),
         Err(e) => respond_error(http::StatusCode::BAD_REQUEST, e).into_response(),
     }
@@ -103,20 +368,46 @@ async fn route_resample(request: Request) -> Response<Body> {
 
 async fn route_upload_image(
     State(state): State<AppState>,
+    Query(query): Query<playback::PlaybackQuery>,
+    Query(transition): Query<TransitionOptions>,
+    Query(resample): Query<imgops::ResampleOptions>,
+    Query(adjust): Query<adjust::Adjustments>,
+    Query(quantize): Query<quantize::QuantizeOptions>,
     request: Request
 ) -> Response<Body> {
+    if let Err(e) = transition.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    if let Err(e) = adjust.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    if let Err(e) = quantize.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let options = match query.apply(playback::PlaybackOptions::default()) {
+        Ok(options) => options,
+        Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
     let body = match request.extract::<Bytes, _>().await {
         Ok(body) => body,
         Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     };
 
-    let frames = match frame::frames_from_image(FRAME_DIMS, &body) {
+    let frames = match frame::frames_from_image(&state.frame_spec, &body, &resample, &adjust, &quantize) {
         Ok(frames) => frames,
         Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e).into_response(),
     };
 
-    match state.frames_tx.send(FramesCmd::Loop(frames)).await {
-        Ok(_) => respond_ok().into_response(),
+    match state.player_tx.send(PlayerCmd::Frames(FramesCmd::Play(frames, options), PlaybackSource::Image, transition)) {
+        Ok(_) => {
+            save_image_content(&state, &body, state::SavedContent::Image{ playback: options, resample, adjust, quantize });
+
+            respond_ok().into_response()
+        },
         Err(e) => {
             error!("Failed to push image to device queue: {}", e);
             respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, String::from("Failed to push image to device queue")).into_response()
@@ -130,12 +421,20 @@ async fn route_solid_color() -> Response<Body> {
 
 async fn route_solid_color_instant(
     State(state): State<AppState>,
-    Path((r, g, b)): Path<(u8, u8, u8)>
+    Path((r, g, b)): Path<(u8, u8, u8)>,
+    Query(transition): Query<TransitionOptions>
 ) -> Response<Body> {
-    let frame = solid::make_frame(FRAME_DIMS, r, g, b);
+    if let Err(e) = transition.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let frame = solid::make_frame(&state.frame_spec, r, g, b);
 
-    match state.frames_tx.send(FramesCmd::Transition(vec![frame])).await {
-        Ok(_) => respond_ok().into_response(),
+    match state.player_tx.send(PlayerCmd::Frames(FramesCmd::Transition(vec![frame::TimedFrame::new(frame)]), PlaybackSource::SolidColor, transition)) {
+        Ok(_) => {
+            save_content(&state, state::SavedContent::SolidColor{ color: [r, g, b] });
+            respond_ok().into_response()
+        },
         Err(e) => {
             error!("Failed to push frames to device queue: {}", e);
             respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, String::from("Failed to push image to device queue")).into_response()
@@ -145,18 +444,30 @@ async fn route_solid_color_instant(
 
 async fn route_solid_color_smooth(
     State(state): State<AppState>,
+    Query(transition): Query<TransitionOptions>,
     Json(transitions): Json<solid::SmoothSolidColor>
 ) -> Response<Body> {
+    if let Err(e) = transition.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
     let mut frames = Vec::new();
+    let last_color = transitions.last().copied();
 
     for transition in transitions {
         let [r, g, b] = transition;
-        let frame = solid::make_frame(FRAME_DIMS, r, g, b);
-        frames.push(frame);
+        let frame = solid::make_frame(&state.frame_spec, r, g, b);
+        frames.push(frame::TimedFrame::new(frame));
     }
 
-    match state.frames_tx.send(FramesCmd::Transition(frames)).await {
-        Ok(()) => respond_ok().into_response(),
+    match state.player_tx.send(PlayerCmd::Frames(FramesCmd::Transition(frames), PlaybackSource::SolidColor, transition)) {
+        Ok(()) => {
+            if let Some(color) = last_color {
+                save_content(&state, state::SavedContent::SolidColor{ color });
+            }
+
+            respond_ok().into_response()
+        },
         Err(e) => {
             error!("Failed to push frames to device queue: {}", e);
             respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to push frames to device queue: {}", e)).into_response()
@@ -164,6 +475,29 @@ async fn route_solid_color_smooth(
     }
 }
 
+async fn route_solid_color_fade(
+    State(state): State<AppState>,
+    Json(fade): Json<solid::SolidFade>
+) -> Response<Body> {
+    if let Err(e) = fade.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let [r, g, b] = fade.color;
+    let target = solid::make_frame(&state.frame_spec, r, g, b);
+
+    match state.player_tx.send(PlayerCmd::Fade(target, fade)) {
+        Ok(()) => {
+            save_content(&state, state::SavedContent::SolidColor{ color: [r, g, b] });
+            respond_ok().into_response()
+        },
+        Err(e) => {
+            error!("Failed to push fade to device queue: {}", e);
+            respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to push fade to device queue: {}", e)).into_response()
+        }
+    }
+}
+
 async fn route_template() -> Response<Body> {
     serve_html_file("web/template.html")
 }
@@ -220,11 +554,88 @@ async fn route_template_load(
     }
 }
 
+fn render_template(
+    state: &AppState,
+    template_name: &str,
+    orig_image: &[u8],
+    resample: imgops::ResampleOptions,
+    adjust: adjust::Adjustments,
+    quantize: quantize::QuantizeOptions
+) -> Result<(), (http::StatusCode, String)> {
+    let resampled_image = match imgops::resample_image(&state.frame_spec, orig_image, &resample, &adjust, &quantize) {
+        Ok(resampled_image) => resampled_image,
+        Err(e) => return Err((http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to resample image: {}", e))),
+    };
+
+    let mut meta = match templates::read_template_meta(&state.templates, template_name) {
+        Ok(meta) => meta,
+        Err(e) => return Err((http::StatusCode::INTERNAL_SERVER_ERROR, e)),
+    };
+    meta.resample = resample;
+    meta.adjust = adjust;
+    meta.quantize = quantize;
+
+    if let Err(e) = templates::write_template(&state.templates, template_name.to_string(), &resampled_image) {
+        return Err((http::StatusCode::INTERNAL_SERVER_ERROR, e));
+    }
+
+    match templates::write_template_meta(&state.templates, template_name, &meta) {
+        Ok(()) => Ok(()),
+        Err(e) => Err((http::StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+async fn route_template_render(
+    State(state): State<AppState>,
+    Path(template_name): Path<String>,
+    Query(resample): Query<imgops::ResampleQuery>,
+    Query(adjust): Query<adjust::AdjustQuery>,
+    Query(quantize): Query<quantize::QuantizeQuery>
+) -> Response<Body> {
+    if template_name.is_empty() {
+        return respond_error(http::StatusCode::BAD_REQUEST, String::from("Invalid template name")).into_response();
+    }
+
+    let orig_image = match templates::read_template_original(&state.templates, &template_name) {
+        Ok(orig_image) => orig_image,
+        Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let meta = match templates::read_template_meta(&state.templates, &template_name) {
+        Ok(meta) => meta,
+        Err(e) => return respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    let resample = resample.apply(meta.resample);
+    let adjust = match adjust.apply(meta.adjust) {
+        Ok(adjust) => adjust,
+        Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let quantize = match quantize.apply(meta.quantize) {
+        Ok(quantize) => quantize,
+        Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    match render_template(&state, &template_name, &orig_image, resample, adjust, quantize) {
+        Ok(()) => respond_ok().into_response(),
+        Err((status, e)) => respond_error(status, e).into_response(),
+    }
+}
 
 async fn route_template_save(
     State(state): State<AppState>,
+    Query(resample): Query<imgops::ResampleOptions>,
+    Query(adjust): Query<adjust::Adjustments>,
+    Query(quantize): Query<quantize::QuantizeOptions>,
     request: Request,
 ) -> Response<Body> {
+    if let Err(e) = adjust.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    if let Err(e) = quantize.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
     let (mut parts, body) = request.into_parts();
     let template_name = match Path::<String>::from_request_parts(&mut parts, &state).await {
         Ok(x) => match x {
@@ -242,45 +653,63 @@ async fn route_template_save(
         Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, format!("Invalid request body: {}", e)).into_response(),
     };
 
-    let resampled_image = match imgops::resample_image(FRAME_DIMS, &orig_image) {
-        Ok(resampled_image) => resampled_image,
-        Err(e) => {
-            return respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to resample image: {}", e)).into_response();
-        },
-    };
+    if let Err((status, e)) = render_template(&state, &template_name, &orig_image, resample, adjust, quantize) {
+        return respond_error(status, e).into_response();
+    }
 
-    match templates::write_template(&state.templates, template_name.clone(), &resampled_image) {
+    match templates::write_template_original(&state.templates, &template_name, &orig_image) {
         Ok(()) => respond_ok().into_response(),
-        Err(e) => {
-            respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
-        }
+        Err(e) => respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
     }
 }
 
 async fn route_template_upload(
     State(state): State<AppState>,
-    Path(template_name): Path<String>
+    Path(template_name): Path<String>,
+    Query(query): Query<playback::PlaybackQuery>,
+    Query(transition): Query<TransitionOptions>
 ) -> Response<Body> {
+    if let Err(e) = transition.validate() {
+        return respond_error(http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+
     if template_name.is_empty() {
         return respond_error(http::StatusCode::BAD_REQUEST, "Template name cannot be empty".to_string()).into_response();
     }
 
-    match templates::read_template(&state.templates, template_name) {
-        Ok(template_bytes) => {
-            match frame::frames_from_image(FRAME_DIMS, &template_bytes) {
-                Ok(frames) => {
-                    match state.frames_tx.send(FramesCmd::Loop(frames)).await {
-                        Ok(_) => respond_ok().into_response(),
-                        Err(e) => respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to push frames to device queue: {}", e)).into_response()
-                    }
-                },
-                Err(e) => {
-                    respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to upload template to device: {}", e)).into_response()
+    let template_bytes = match templates::read_template(&state.templates, template_name.clone()) {
+        Ok(template_bytes) => template_bytes,
+        Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, format!("Failed to load template: {}", e)).into_response(),
+    };
+
+    let mut meta = match templates::read_template_meta(&state.templates, &template_name) {
+        Ok(meta) => meta,
+        Err(e) => return respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    let options = match query.apply(meta.playback) {
+        Ok(options) => options,
+        Err(e) => return respond_error(http::StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if !query.is_empty() {
+        meta.playback = options;
+        if let Err(e) = templates::write_template_meta(&state.templates, &template_name, &meta) {
+            return respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+    }
+
+    match frame::frames_from_image(&state.frame_spec, &template_bytes, &imgops::ResampleOptions::default(), &adjust::Adjustments::default(), &quantize::QuantizeOptions::default()) {
+        Ok(frames) => {
+            let source = PlaybackSource::Template{ name: template_name.clone() };
+            match state.player_tx.send(PlayerCmd::Frames(FramesCmd::Play(frames, options), source, transition)) {
+                Ok(_) => {
+                    save_content(&state, state::SavedContent::Template{ name: template_name, playback: options });
+                    respond_ok().into_response()
                 },
+                Err(e) => respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to push frames to device queue: {}", e)).into_response()
             }
         },
         Err(e) => {
-            respond_error(http::StatusCode::BAD_REQUEST, format!("Failed to load template: {}", e)).into_response()
+            respond_error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to upload template to device: {}", e)).into_response()
         },
     }
 }
@@ -307,110 +736,107 @@ async fn main() {
         panic!("Templates directory {} does not exist", cfg.templates);
     }
 
-    let mut device = match cfg.device {
-        Some(device) => match device::open_device(device.as_str()) {
-            Ok(device) => Some(device),
-            Err(e) => panic!("Failed to open device: {}", e),
+    let sink::Output{ sink, status: device_status, ports, recorded_frames } = match sink::open_sink(&cfg) {
+        Ok(output) => output,
+        Err(e) => panic!("Failed to open output: {}", e),
+    };
+    info!("Sending frames to {} output", sink.name());
+
+    let device_info = match device_status.lock().unwrap().info.clone() {
+        Some(info) if info.width == 0 || info.height == 0 => {
+            warn!("Ignoring invalid matrix size {}x{} reported by device", info.width, info.height);
+            None
+        },
+        info => info,
+    };
+    let frame_spec = match (cfg.matrix, device_info) {
+        (Some(matrix), Some(info)) => {
+            if (matrix.width, matrix.height) != (info.width, info.height) {
+                warn!("Configured matrix is {}x{} but device reports {}x{}", matrix.width, matrix.height, info.width, info.height);
+            }
+
+            (matrix.width, matrix.height)
+        },
+        (Some(matrix), None) => (matrix.width, matrix.height),
+        (None, Some(info)) => (info.width, info.height),
+        (None, None) => DEFAULT_FRAME_DIMS,
+    }.into_framespec();
+    let led_map = match &cfg.led_map {
+        Some(path) => match ledmap::read_led_map(&PathBuf::from(path), frame_spec.width, frame_spec.height) {
+            Ok(led_map) => {
+                info!("Loaded LED map {} with {} LEDs", path, led_map.len());
+                Some(std::sync::Arc::new(led_map))
+            },
+            Err(e) => panic!("Failed to load LED map: {}", e),
         },
         None => None,
     };
+    let frame_spec = frame::FrameSpec{ layout: cfg.layout, led_map, ..frame_spec };
+    info!("Matrix size is {}x{}", frame_spec.width, frame_spec.height);
 
     let listener = tokio::net::TcpListener::bind(cfg.host)
         .await
         .expect("Could not bind listening socket");
 
-    let (frames_tx, mut frames_rx) = tokio::sync::mpsc::channel(16);
-    tokio::spawn(async move {
-        let mut cmd = FramesCmd::Empty;
-        let mut frame_idx = 0;
-
-        loop {
-            let timeout = match &cmd{
-                FramesCmd::Empty => Duration::from_secs(10_000),
-                FramesCmd::Loop(frames) => {
-                    if frames.len() > 1 || (frames.len() == 1 && frame_idx == 0) {
-                        Duration::from_millis(MILLIS_PER_FRAME)
-                    } else {
-                        Duration::from_secs(10_000)
-                    }
-                },
-                FramesCmd::Transition(frames) => {
-                    if frame_idx == frames.len() {
-                        Duration::from_secs(10_000)
-                    } else {
-                        Duration::from_millis(MILLIS_PER_FRAME)
-                    }
-                },
-            };
-
-            tokio::select! {
-                result = tokio::time::timeout(timeout, frames_rx.recv()) => {
-                    match result {
-                        Ok(maybe_new_cmd) => {
-                            if let Some(new_cmd) = maybe_new_cmd {
-                                cmd = new_cmd;
-                                frame_idx = 0;
-                            }
-                        },
-                        Err(_) => {
-                            if let Some(device) = &mut device {
-                                match &cmd {
-                                    FramesCmd::Loop(frames) => {
-                                        let frame = &frames[frame_idx];
-
-                                        match device::upload_frame(device, frame) {
-                                            Ok(()) => {
-                                                frame_idx = (frame_idx + 1) % frames.len();
-                                            },
-                                            Err(e) => {
-                                                error!("Failed to upload frame to device: {}", e);
-
-                                                cmd = FramesCmd::Empty;
-                                            }
-                                        }
-                                    },
-                                    FramesCmd::Transition(frames) => {
-                                        if frame_idx < frames.len() {
-                                            let frame = &frames[frame_idx];
-
-                                            match device::upload_frame(device, frame) {
-                                                Ok(()) => {
-                                                    frame_idx += 1;
-                                                },
-                                                Err(e) => {
-                                                    error!("Failed to upload frame to device: {}", e);
-
-                                                    cmd = FramesCmd::Empty;
-                                                }
-                                            }
-                                        }
-                                    },
-                                    _ => (),
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    });
+    let state_path = PathBuf::from(cfg.state.clone());
+    let saved_state = state::read_state(&state_path);
+
+    let power = power::PowerLimiter::new(&cfg.power);
+    let power_status = power.status();
+    let pipeline = pipeline::OutputPipeline::new(
+        frame_spec.layout,
+        saved_state.brightness,
+        calibration::Calibration::new(&cfg.calibration),
+        power,
+        cfg.dither
+    );
+
+    let (player_tx, playback_stats, playback_status) = player::spawn_player(sink, pipeline, frame::Canvas::new(&frame_spec));
 
     let app_state = AppState{
-        frames_tx,
+        device_status,
+        device_ports: ports,
+        frame_spec,
+        player_tx,
+        playback_stats,
+        playback_status,
+        power_status,
+        recorded_frames,
+        saved_state: Arc::new(Mutex::new(saved_state)),
+        state_path,
         templates,
     };
 
+    if let Err(e) = restore_content(&app_state) {
+        warn!("Could not restore previous content: {}", e);
+    }
+
     let app = axum::Router::new()
         .route("/", axum::routing::get(route_index))
+        .route("/brightness", axum::routing::get(route_brightness).post(route_brightness_set))
+        .route("/device/ports", axum::routing::get(route_device_ports))
+        .route("/device/status", axum::routing::get(route_device_status))
+        .route("/effect/list", axum::routing::get(route_effect_list))
+        .route("/effect/{name}", axum::routing::post(route_effect_start))
+        .route("/output/frames", axum::routing::get(route_output_frames))
+        .route("/output/stats", axum::routing::get(route_output_stats))
+        .route("/playback/pause", axum::routing::post(route_playback_pause))
+        .route("/playback/resume", axum::routing::post(route_playback_resume))
+        .route("/playback/seek/{frame}", axum::routing::post(route_playback_seek))
+        .route("/playback/status", axum::routing::get(route_playback_status))
+        .route("/playback/step/{frames}", axum::routing::post(route_playback_step))
+        .route("/power", axum::routing::get(route_power))
         .route("/resample-image", axum::routing::post(route_resample))
         .route("/upload-image", axum::routing::post(route_upload_image))
         .route("/solid-color", axum::routing::get(route_solid_color))
+        .route("/solid-color/fade", axum::routing::post(route_solid_color_fade))
         .route("/solid-color/instant/{r}/{g}/{b}", axum::routing::post(route_solid_color_instant))
         .route("/solid-color/smooth", axum::routing::post(route_solid_color_smooth))
         .route("/template", axum::routing::get(route_template))
         .route("/template/delete/{name}", axum::routing::post(route_template_delete))
         .route("/template/list", axum::routing::get(route_template_list))
         .route("/template/load/{name}", axum::routing::get(route_template_load))
+        .route("/template/render/{name}", axum::routing::post(route_template_render))
         .route("/template/save/{name}", axum::routing::post(route_template_save))
         .route("/template/upload/{name}", axum::routing::post(route_template_upload))
         .nest_service("/static", tower_http::services::ServeDir::new("web/static"))
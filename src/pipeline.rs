@@ -0,0 +1,55 @@
+use std::time::{Duration, Instant};
+
+use crate::brightness::{self, Brightness};
+use crate::calibration::Calibration;
+use crate::dither::TemporalDither;
+use crate::frame::Frame;
+use crate::layout::PixelLayout;
+use crate::power::PowerLimiter;
+
+pub struct OutputPipeline {
+    layout: PixelLayout,
+    brightness: Brightness,
+    rendered_brightness: f32,
+    calibration: Calibration,
+    power: PowerLimiter,
+    dither: TemporalDither,
+    residual: bool,
+}
+impl OutputPipeline {
+    pub fn new(layout: PixelLayout, brightness: f32, calibration: Calibration, power: PowerLimiter, dither: bool) -> Self {
+        OutputPipeline{
+            layout,
+            brightness: Brightness::new(brightness),
+            rendered_brightness: brightness,
+            calibration,
+            power,
+            dither: TemporalDither::new(dither),
+            residual: false,
+        }
+    }
+
+    pub fn set_brightness(&mut self, level: f32, fade: Duration, now: Instant) {
+        self.brightness.fade_to(level, fade, now);
+    }
+
+    pub fn needs_refresh(&self, now: Instant) -> bool {
+        self.residual || self.brightness.level(now) != self.rendered_brightness
+    }
+
+    pub fn render(&mut self, frame: &Frame, now: Instant) -> Frame {
+        let level = self.brightness.level(now);
+        self.rendered_brightness = level;
+
+        let mut linear: Vec<f32> = frame.iter().map(|value| *value as f32).collect();
+        brightness::apply_brightness(&mut linear, level);
+        self.calibration.apply(&mut linear);
+        self.power.apply(&mut linear);
+
+        let (mut output, residual) = self.dither.quantize(&linear);
+        self.residual = residual;
+        self.layout.apply_color_order(&mut output);
+
+        output
+    }
+}
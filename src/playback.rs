@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+const MAX_SPEED: f32 = 16.0;
+
+fn default_count() -> u32 {
+    1
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopMode {
+    #[default]
+    Forever,
+    Times,
+    PingPong,
+    Once,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct PlaybackOptions {
+    #[serde(default)]
+    pub mode: LoopMode,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+}
+impl PlaybackOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.count == 0 {
+            return Err(String::from("Loop count must be at least 1"));
+        }
+
+        if !self.speed.is_finite() || self.speed <= 0.0 || self.speed > MAX_SPEED {
+            return Err(format!("Speed must be greater than 0.0 and at most {}, got {}", MAX_SPEED, self.speed));
+        }
+
+        Ok(())
+    }
+}
+impl std::default::Default for PlaybackOptions {
+    fn default() -> Self {
+        PlaybackOptions{
+            mode: LoopMode::default(),
+            count: default_count(),
+            speed: default_speed(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PlaybackQuery {
+    pub mode: Option<LoopMode>,
+    pub count: Option<u32>,
+    pub speed: Option<f32>,
+}
+impl PlaybackQuery {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.count.is_none() && self.speed.is_none()
+    }
+
+    pub fn apply(&self, options: PlaybackOptions) -> Result<PlaybackOptions, String> {
+        let options = PlaybackOptions{
+            mode: self.mode.unwrap_or(options.mode),
+            count: self.count.unwrap_or(options.count),
+            speed: self.speed.unwrap_or(options.speed),
+        };
+
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Default, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlaybackSource {
+    #[default]
+    None,
+    Effect { name: String },
+    Image,
+    SolidColor,
+    Template { name: String },
+}
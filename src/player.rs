@@ -0,0 +1,531 @@
+use serde::Serialize;
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::error;
+
+use crate::effect::Effect;
+use crate::frame::{Canvas, DEFAULT_FRAME_DURATION, Frame, Frames, TimedFrame};
+use crate::pipeline::OutputPipeline;
+use crate::playback::{LoopMode, PlaybackOptions, PlaybackSource};
+use crate::sink::DisplaySink;
+use crate::solid::{self, SolidFade};
+use crate::transition::{Transition, TransitionOptions};
+
+const CONNECTION_POLL: Duration = Duration::from_millis(250);
+const LATE_THRESHOLD: Duration = Duration::from_millis(5);
+const MIN_FRAME_DURATION: Duration = Duration::from_millis(1);
+const STATS_WINDOW: Duration = Duration::from_secs(1);
+
+pub enum FramesCmd {
+    Empty,
+    Transition(Frames),
+    Play(Frames, PlaybackOptions),
+    Effect(Box<dyn Effect>),
+}
+
+pub enum PlayerCmd {
+    Frames(FramesCmd, PlaybackSource, TransitionOptions),
+    Brightness(f32, Duration),
+    Fade(Frame, SolidFade),
+    Pause,
+    Resume,
+    Step(i64),
+    Seek(usize),
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct PlaybackStats {
+    pub target_fps: f64,
+    pub achieved_fps: f64,
+    pub frames_written: u64,
+    pub late_frames: u64,
+    pub dropped_frames: u64,
+}
+
+pub type SharedPlaybackStats = Arc<Mutex<PlaybackStats>>;
+
+#[derive(Clone, Default, Serialize)]
+pub struct PlaybackStatus {
+    pub source: PlaybackSource,
+    pub playing: bool,
+    pub paused: bool,
+    pub frame_index: usize,
+    pub frame_count: usize,
+    pub fps: f64,
+}
+
+pub type SharedPlaybackStatus = Arc<Mutex<PlaybackStatus>>;
+
+struct Player {
+    sink: Box<dyn DisplaySink>,
+    pipeline: OutputPipeline,
+    cmd: FramesCmd,
+    source: PlaybackSource,
+    frame_idx: usize,
+    shown_idx: usize,
+    effect_frames: u32,
+    effect_start: Instant,
+    pass: u32,
+    reverse: bool,
+    previous: Option<(FramesCmd, PlaybackSource)>,
+    deadline: Option<Instant>,
+    paused: bool,
+    paused_at: Option<Instant>,
+    remaining: Option<Duration>,
+    canvas: Canvas,
+    transition: Option<Transition>,
+    last_frame: Option<Frame>,
+    last_write: Instant,
+    write_failed: bool,
+    stats: SharedPlaybackStats,
+    status: SharedPlaybackStatus,
+    window_start: Instant,
+    window_frames: u32,
+}
+impl Player {
+    fn frames(&self) -> Option<&Frames> {
+        match &self.cmd {
+            FramesCmd::Empty | FramesCmd::Effect(_) => None,
+            FramesCmd::Transition(frames) | FramesCmd::Play(frames, _) => Some(frames),
+        }
+    }
+
+    fn speed(&self) -> f32 {
+        match &self.cmd {
+            FramesCmd::Play(_, options) => options.speed,
+            _ => 1.0,
+        }
+    }
+
+    fn frame_duration(&self, frame: &TimedFrame) -> Duration {
+        frame.duration.div_f32(self.speed()).max(MIN_FRAME_DURATION)
+    }
+
+    fn set_cmd(&mut self, cmd: FramesCmd, source: PlaybackSource, transition: TransitionOptions, now: Instant) {
+        let previous = std::mem::replace(&mut self.cmd, cmd);
+        let previous_source = std::mem::replace(&mut self.source, source);
+        self.previous = match (&self.cmd, previous) {
+            (FramesCmd::Play(_, options), _) if options.mode != LoopMode::Once => None,
+            (FramesCmd::Play(..), FramesCmd::Empty) => None,
+            (FramesCmd::Play(..), FramesCmd::Play(_, options)) if options.mode == LoopMode::Once => self.previous.take(),
+            (FramesCmd::Play(..), previous) => Some((previous, previous_source)),
+            _ => None,
+        };
+        self.frame_idx = 0;
+        self.shown_idx = 0;
+        self.effect_frames = 0;
+        self.effect_start = now;
+        self.pass = 0;
+        self.reverse = false;
+        self.paused = false;
+        self.paused_at = None;
+        self.remaining = None;
+        self.deadline = match (&self.cmd, self.frames()) {
+            (FramesCmd::Effect(_), _) => Some(now),
+            (_, Some(frames)) if !frames.is_empty() => Some(now),
+            _ => None,
+        };
+
+        self.transition = match &self.last_frame {
+            Some(from) if self.deadline.is_some() && !transition.is_none() => {
+                self.deadline = None;
+                Some(Transition::new(&transition, from.clone(), now))
+            },
+            _ => None,
+        };
+    }
+
+    fn reconnected(&mut self, now: Instant) {
+        let len = match self.frames() {
+            Some(frames) if !frames.is_empty() => frames.len(),
+            _ => return,
+        };
+
+        if self.paused {
+            self.frame_idx = self.shown_idx.min(len - 1);
+        } else {
+            self.frame_idx = self.frame_idx.min(len - 1);
+        }
+        self.deadline = Some(now);
+    }
+
+    fn pause(&mut self, now: Instant) {
+        if self.paused {
+            return;
+        }
+
+        self.paused = true;
+        self.paused_at = Some(now);
+        self.remaining = self.deadline.take().map(|deadline| deadline.saturating_duration_since(now));
+    }
+
+    fn resume(&mut self, now: Instant) {
+        if !self.paused {
+            return;
+        }
+
+        self.paused = false;
+        if let Some(paused_at) = self.paused_at.take() {
+            self.effect_start += now - paused_at;
+        }
+        self.deadline = self.remaining.take().map(|remaining| now + remaining);
+    }
+
+    fn seek(&mut self, frame_idx: usize, now: Instant) {
+        let len = match self.frames() {
+            Some(frames) if !frames.is_empty() => frames.len(),
+            _ => return,
+        };
+
+        self.frame_idx = frame_idx.min(len - 1);
+        self.deadline = Some(now);
+    }
+
+    fn step(&mut self, frames: i64, now: Instant) {
+        let len = match self.frames() {
+            Some(frames) if !frames.is_empty() => frames.len() as i64,
+            _ => return,
+        };
+
+        self.pause(now);
+        self.seek((self.shown_idx as i64 + frames.rem_euclid(len)).rem_euclid(len) as usize, now);
+    }
+
+    fn current_frame(&self) -> Option<&TimedFrame> {
+        self.frames().and_then(|frames| frames.get(self.frame_idx))
+    }
+
+    fn content_frame(&self, now: Instant) -> Option<Frame> {
+        match &self.cmd {
+            FramesCmd::Effect(effect) => Some(effect.render(&self.canvas, self.effect_elapsed(now))),
+            _ => self.current_frame().map(|current| current.frame.clone()),
+        }
+    }
+
+    fn effect_elapsed(&self, now: Instant) -> Duration {
+        self.paused_at.unwrap_or(now).saturating_duration_since(self.effect_start)
+    }
+
+    fn frame_rate(&self) -> f64 {
+        match (&self.cmd, self.current_frame()) {
+            _ if self.paused || self.deadline.is_none() => 0.0,
+            (FramesCmd::Effect(_), _) => 1.0 / DEFAULT_FRAME_DURATION.as_secs_f64(),
+            (_, Some(current)) => 1.0 / self.frame_duration(current).as_secs_f64(),
+            _ => 0.0,
+        }
+    }
+
+    fn schedule(&mut self, deadline: Option<Instant>, now: Instant) {
+        if self.paused {
+            self.remaining = deadline.map(|deadline| deadline.saturating_duration_since(now));
+        } else {
+            self.deadline = deadline;
+        }
+    }
+
+    fn has_next_frame(&self) -> bool {
+        match &self.cmd {
+            FramesCmd::Empty => false,
+            FramesCmd::Effect(_) => true,
+            FramesCmd::Transition(frames) => self.frame_idx + 1 < frames.len(),
+            FramesCmd::Play(frames, options) => match options.mode {
+                LoopMode::Forever | LoopMode::PingPong => frames.len() > 1,
+                LoopMode::Times => self.frame_idx + 1 < frames.len() || (frames.len() > 1 && self.pass + 1 < options.count),
+                LoopMode::Once => self.frame_idx + 1 < frames.len() || self.previous.is_some(),
+            },
+        }
+    }
+
+    fn advance(&mut self) {
+        match &self.cmd {
+            FramesCmd::Empty | FramesCmd::Effect(_) => (),
+            FramesCmd::Transition(_) => {
+                self.frame_idx += 1;
+            },
+            FramesCmd::Play(frames, options) => match options.mode {
+                LoopMode::PingPong => {
+                    if self.frame_idx + 1 == frames.len() {
+                        self.reverse = true;
+                    } else if self.frame_idx == 0 {
+                        self.reverse = false;
+                    }
+
+                    if self.reverse {
+                        self.frame_idx -= 1;
+                    } else {
+                        self.frame_idx += 1;
+                    }
+                },
+                LoopMode::Once if self.frame_idx + 1 == frames.len() => {
+                    if let Some((previous, source)) = self.previous.take() {
+                        self.restore(previous, source);
+                    }
+                },
+                _ if self.frame_idx + 1 == frames.len() => {
+                    self.frame_idx = 0;
+                    self.pass += 1;
+                },
+                _ => {
+                    self.frame_idx += 1;
+                },
+            },
+        }
+    }
+
+    fn restore(&mut self, cmd: FramesCmd, source: PlaybackSource) {
+        // A transition that already ran shouldn't replay from its stale start.
+        self.frame_idx = match &cmd {
+            FramesCmd::Transition(frames) => frames.len().saturating_sub(1),
+            _ => 0,
+        };
+        self.cmd = cmd;
+        self.source = source;
+        self.pass = 0;
+        self.reverse = false;
+    }
+
+    fn refresh(&mut self, now: Instant) -> bool {
+        let frame = match &self.last_frame {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        let output = self.pipeline.render(frame, now);
+        if let Err(e) = self.sink.write_frame(&output) {
+            error!("Failed to upload frame to device: {}", e);
+            self.write_failed = true;
+            return false;
+        }
+
+        self.write_failed = false;
+        self.last_write = now;
+        self.window_frames += 1;
+        self.stats.lock().unwrap().frames_written += 1;
+
+        true
+    }
+
+    fn refresh_at(&self, now: Instant) -> Option<Instant> {
+        if self.transition.is_some() || (self.last_frame.is_some() && self.pipeline.needs_refresh(now)) {
+            Some(self.last_write + DEFAULT_FRAME_DURATION)
+        } else {
+            None
+        }
+    }
+
+    fn tick(&mut self, now: Instant) {
+        if let Some(transition) = &self.transition {
+            if !transition.is_done(now) {
+                if now >= self.last_write + DEFAULT_FRAME_DURATION
+                    && let Some(target) = self.content_frame(now) {
+                    let frame = transition.render(&self.canvas, &target, now);
+                    self.last_frame = Some(frame);
+                    self.refresh(now);
+                }
+                return;
+            }
+
+            self.transition = None;
+            self.schedule(Some(now), now);
+        }
+
+        let mut deadline = match self.deadline {
+            Some(deadline) if now >= deadline => deadline,
+            _ => {
+                if self.refresh_at(now).is_some_and(|refresh_at| now >= refresh_at) {
+                    self.refresh(now);
+                }
+                return;
+            },
+        };
+
+        if let FramesCmd::Effect(effect) = &self.cmd {
+            self.last_frame = Some(effect.render(&self.canvas, self.effect_elapsed(now)));
+            if !self.refresh(now) {
+                return;
+            }
+
+            self.effect_frames += 1;
+            self.deadline = None;
+            self.schedule(Some((deadline + DEFAULT_FRAME_DURATION).max(now)), now);
+            return;
+        }
+
+        let mut dropped = 0;
+        while let Some(current) = self.current_frame() {
+            let duration = self.frame_duration(current);
+            if now < deadline + duration || !self.has_next_frame() {
+                break;
+            }
+
+            self.advance();
+            deadline += duration;
+            dropped += 1;
+        }
+
+        let current = match self.current_frame() {
+            Some(current) => current,
+            None => return,
+        };
+        let duration = self.frame_duration(current);
+        self.last_frame = Some(current.frame.clone());
+
+        if !self.refresh(now) {
+            return;
+        }
+
+        self.shown_idx = self.frame_idx;
+        let next_deadline = if self.has_next_frame() {
+            self.advance();
+            Some(deadline + duration)
+        } else {
+            None
+        };
+        self.deadline = None;
+        self.schedule(next_deadline, now);
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.dropped_frames += dropped;
+        if now - deadline > LATE_THRESHOLD {
+            stats.late_frames += 1;
+        }
+    }
+
+    fn handle_cmd(&mut self, cmd: PlayerCmd, now: Instant) {
+        match cmd {
+            PlayerCmd::Frames(cmd, source, transition) => self.set_cmd(cmd, source, transition, now),
+            PlayerCmd::Brightness(level, fade) => self.pipeline.set_brightness(level, fade, now),
+            PlayerCmd::Fade(target, fade) => {
+                let from = match &self.last_frame {
+                    Some(frame) if frame.len() == target.len() => frame.clone(),
+                    _ => vec![0; target.len()],
+                };
+
+                let frames = solid::fade_frames(&from, &target, fade.duration_ms, fade.easing, fade.space);
+                self.set_cmd(FramesCmd::Transition(frames), PlaybackSource::SolidColor, TransitionOptions::default(), now);
+            },
+            PlayerCmd::Pause => self.pause(now),
+            PlayerCmd::Resume => self.resume(now),
+            PlayerCmd::Step(frames) => self.step(frames, now),
+            PlayerCmd::Seek(frame_idx) => self.seek(frame_idx, now),
+        }
+    }
+
+    fn update_status(&self) {
+        let frame_count = self.frames().map_or(0, |frames| frames.len());
+        let playing = !self.paused && (self.deadline.is_some() || self.transition.is_some());
+
+        let mut status = self.status.lock().unwrap();
+        status.source = self.source.clone();
+        status.playing = playing;
+        status.paused = self.paused;
+        status.frame_count = frame_count;
+        if let FramesCmd::Effect(_) = &self.cmd {
+            status.frame_index = self.effect_frames as usize;
+            status.fps = if playing { 1.0 / DEFAULT_FRAME_DURATION.as_secs_f64() } else { 0.0 };
+            return;
+        }
+
+        status.frame_index = self.shown_idx.min(frame_count.saturating_sub(1));
+        status.fps = match self.frames().and_then(|frames| frames.get(status.frame_index)) {
+            Some(current) if playing => 1.0 / self.frame_duration(current).as_secs_f64(),
+            _ => 0.0,
+        };
+    }
+
+    fn update_stats(&mut self, now: Instant) {
+        let elapsed = now - self.window_start;
+        if elapsed < STATS_WINDOW {
+            return;
+        }
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.achieved_fps = self.window_frames as f64 / elapsed.as_secs_f64();
+        stats.target_fps = self.frame_rate();
+
+        self.window_start = now;
+        self.window_frames = 0;
+    }
+
+    fn run(&mut self, player_rx: Receiver<PlayerCmd>) {
+        // While the sink is gone or failing, deadlines stay in the past, so
+        // poll at the connection rate instead of spinning on them.
+        let mut stalled = false;
+
+        loop {
+            let now = Instant::now();
+            let wake_at = match (self.deadline, self.refresh_at(now)) {
+                (Some(deadline), Some(refresh_at)) => Some(deadline.min(refresh_at)),
+                (deadline, refresh_at) => deadline.or(refresh_at),
+            };
+            let wait = match wake_at {
+                Some(wake_at) if !stalled => wake_at.saturating_duration_since(now).min(CONNECTION_POLL),
+                _ => CONNECTION_POLL,
+            };
+
+            match player_rx.recv_timeout(wait) {
+                Ok(cmd) => self.handle_cmd(cmd, Instant::now()),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let now = Instant::now();
+            let was_connected = self.sink.is_connected();
+            stalled = !self.sink.reconnect();
+            if !stalled {
+                if !was_connected {
+                    self.reconnected(now);
+                }
+
+                self.tick(now);
+                stalled = self.write_failed;
+            }
+
+            self.update_stats(now);
+            self.update_status();
+        }
+    }
+}
+
+pub fn spawn_player(sink: Box<dyn DisplaySink>, pipeline: OutputPipeline, canvas: Canvas) -> (Sender<PlayerCmd>, SharedPlaybackStats, SharedPlaybackStatus) {
+    let (player_tx, player_rx) = std::sync::mpsc::channel();
+    let stats = Arc::new(Mutex::new(PlaybackStats::default()));
+    let status = Arc::new(Mutex::new(PlaybackStatus::default()));
+
+    let mut player = Player{
+        sink,
+        pipeline,
+        cmd: FramesCmd::Empty,
+        source: PlaybackSource::None,
+        frame_idx: 0,
+        shown_idx: 0,
+        effect_frames: 0,
+        effect_start: Instant::now(),
+        pass: 0,
+        reverse: false,
+        previous: None,
+        deadline: None,
+        paused: false,
+        paused_at: None,
+        remaining: None,
+        canvas,
+        transition: None,
+        last_frame: None,
+        last_write: Instant::now(),
+        write_failed: false,
+        stats: stats.clone(),
+        status: status.clone(),
+        window_start: Instant::now(),
+        window_frames: 0,
+    };
+
+    std::thread::Builder::new()
+        .name(String::from("output"))
+        .spawn(move || player.run(player_rx))
+        .expect("Could not start output thread");
+
+    (player_tx, stats, status)
+}
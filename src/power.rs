@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use std::sync::{Arc, Mutex};
+
+fn default_ma_per_led() -> f32 {
+    60.0
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PowerConfig {
+    #[serde(default = "default_ma_per_led")]
+    pub ma_per_led: f32,
+    pub budget_ma: Option<f32>,
+}
+impl PowerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.ma_per_led.is_finite() || self.ma_per_led <= 0.0 {
+            return Err(format!("Current per LED must be greater than zero, got {}", self.ma_per_led));
+        }
+
+        if let Some(budget_ma) = self.budget_ma
+            && (!budget_ma.is_finite() || budget_ma <= 0.0) {
+            return Err(format!("Current budget must be greater than zero, got {}", budget_ma));
+        }
+
+        Ok(())
+    }
+}
+impl std::default::Default for PowerConfig {
+    fn default() -> Self {
+        PowerConfig{
+            ma_per_led: default_ma_per_led(),
+            budget_ma: None,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct PowerStatus {
+    pub budget_ma: Option<f32>,
+    pub requested_ma: f32,
+    pub output_ma: f32,
+    pub scale: f32,
+    pub limited: bool,
+    pub limited_frames: u64,
+}
+
+pub type SharedPowerStatus = Arc<Mutex<PowerStatus>>;
+
+pub struct PowerLimiter {
+    cfg: PowerConfig,
+    status: SharedPowerStatus,
+}
+impl PowerLimiter {
+    pub fn new(cfg: &PowerConfig) -> Self {
+        let status = PowerStatus{
+            budget_ma: cfg.budget_ma,
+            scale: 1.0,
+            ..PowerStatus::default()
+        };
+
+        PowerLimiter{
+            cfg: cfg.clone(),
+            status: Arc::new(Mutex::new(status)),
+        }
+    }
+
+    pub fn status(&self) -> SharedPowerStatus {
+        self.status.clone()
+    }
+
+    fn estimate_ma(&self, frame: &[f32]) -> f32 {
+        let sum: f32 = frame.iter().sum();
+
+        sum / (255.0 * 3.0) * self.cfg.ma_per_led
+    }
+
+    pub fn apply(&self, frame: &mut [f32]) {
+        let requested_ma = self.estimate_ma(frame);
+
+        let scale = match self.cfg.budget_ma {
+            Some(budget_ma) if requested_ma > budget_ma => budget_ma / requested_ma,
+            _ => 1.0,
+        };
+        let limited = scale < 1.0;
+
+        let output_ma = if limited {
+            for value in frame.iter_mut() {
+                *value *= scale;
+            }
+
+            self.estimate_ma(frame)
+        } else {
+            requested_ma
+        };
+
+        let mut status = self.status.lock().unwrap();
+        status.requested_ma = requested_ma;
+        status.output_ma = output_ma;
+        status.scale = scale;
+        status.limited = limited;
+        if limited {
+            status.limited_frames += 1;
+        }
+    }
+}
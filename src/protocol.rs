@@ -0,0 +1,285 @@
+use serde::Serialize;
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+const MAGIC: [u8; 2] = [0x4e, 0x4c];
+const HEADER_LEN: usize = 9;
+const CRC_LEN: usize = 4;
+const MAX_PAYLOAD_LEN: usize = 1 << 20;
+const MAX_SYNC_BYTES: usize = 64 * 1024;
+const FRAME_ATTEMPTS: usize = 3;
+
+const MSG_HELLO: u8 = 0x01;
+const MSG_FRAME: u8 = 0x02;
+const MSG_ACK: u8 = 0x06;
+const MSG_NAK: u8 = 0x15;
+const MSG_HELLO_REPLY: u8 = 0x81;
+
+#[derive(Clone, Serialize)]
+pub struct DeviceInfo {
+    pub width: u16,
+    pub height: u16,
+    pub firmware: String,
+}
+
+struct Message {
+    kind: u8,
+    seq: u16,
+    payload: Vec<u8>,
+}
+
+// Timeouts and corrupt replies can be recovered from by resending, an I/O
+// error means the port itself is gone.
+#[derive(Debug)]
+enum ReadError {
+    Timeout,
+    Corrupt(String),
+    Io(String),
+}
+impl From<ReadError> for String {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::Timeout => String::from("Timed out waiting for device reply"),
+            ReadError::Corrupt(e) | ReadError::Io(e) => e,
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn encode_message(kind: u8, seq: u16, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+    message.extend_from_slice(&MAGIC);
+    message.push(kind);
+    message.extend_from_slice(&seq.to_le_bytes());
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(payload);
+
+    let crc = crc32(&message);
+    message.extend_from_slice(&crc.to_le_bytes());
+
+    message
+}
+
+fn read_exact<R: Read>(port: &mut R, buf: &mut [u8]) -> Result<(), ReadError> {
+    match port.read_exact(buf) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Err(ReadError::Timeout),
+        Err(e) => Err(ReadError::Io(format!("Failed to read from device: {}", e))),
+    }
+}
+
+fn read_message<R: Read>(port: &mut R) -> Result<Message, ReadError> {
+    let mut header = [0u8; HEADER_LEN];
+
+    let mut skipped = 0;
+    loop {
+        read_exact(port, &mut header[1..2])?;
+        if header[..2] == MAGIC {
+            break;
+        }
+
+        header[0] = header[1];
+        skipped += 1;
+        if skipped > MAX_SYNC_BYTES {
+            return Err(ReadError::Corrupt(String::from("Lost synchronisation with device")));
+        }
+    }
+
+    read_exact(port, &mut header[2..])?;
+
+    let kind = header[2];
+    let seq = u16::from_le_bytes([header[3], header[4]]);
+    let len = u32::from_le_bytes([header[5], header[6], header[7], header[8]]) as usize;
+    if len > MAX_PAYLOAD_LEN {
+        return Err(ReadError::Corrupt(format!("Device reply is too large ({} bytes)", len)));
+    }
+
+    let mut body = vec![0u8; len + CRC_LEN];
+    read_exact(port, &mut body)?;
+
+    let crc_bytes = body.split_off(len);
+    let crc = u32::from_le_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+
+    let mut checked = header.to_vec();
+    checked.extend_from_slice(&body);
+    if crc32(&checked) != crc {
+        return Err(ReadError::Corrupt(String::from("Device reply failed checksum")));
+    }
+
+    Ok(Message{ kind, seq, payload: body })
+}
+
+fn write_message(port: &mut Box<dyn serialport::SerialPort>, message: &[u8]) -> Result<(), String> {
+    if let Err(e) = port.write_all(message) {
+        return Err(e.to_string());
+    }
+
+    if let Err(e) = port.flush() {
+        return Err(e.to_string());
+    }
+
+    Ok(())
+}
+
+fn with_read_timeout<T, F>(port: &mut Box<dyn serialport::SerialPort>, timeout: Duration, f: F) -> Result<T, String>
+where
+    F: FnOnce(&mut Box<dyn serialport::SerialPort>) -> Result<T, String>
+{
+    let write_timeout = port.timeout();
+    if let Err(e) = port.set_timeout(timeout) {
+        return Err(format!("Cannot set read timeout: {}", e));
+    }
+
+    let result = f(port);
+
+    if let Err(e) = port.set_timeout(write_timeout) {
+        return Err(format!("Cannot restore write timeout: {}", e));
+    }
+
+    result
+}
+
+pub fn handshake(port: &mut Box<dyn serialport::SerialPort>, timeout: Duration) -> Result<DeviceInfo, String> {
+    if let Err(e) = port.clear(serialport::ClearBuffer::Input) {
+        return Err(format!("Cannot clear input buffer: {}", e));
+    }
+
+    write_message(port, &encode_message(MSG_HELLO, 0, &[]))?;
+
+    let reply = with_read_timeout(port, timeout, |port| loop {
+        let message = read_message(port)?;
+        if message.kind == MSG_HELLO_REPLY {
+            break Ok(message);
+        }
+    })?;
+
+    if reply.payload.len() < 4 {
+        return Err(String::from("Handshake reply is too short"));
+    }
+
+    Ok(DeviceInfo{
+        width: u16::from_le_bytes([reply.payload[0], reply.payload[1]]),
+        height: u16::from_le_bytes([reply.payload[2], reply.payload[3]]),
+        firmware: String::from_utf8_lossy(&reply.payload[4..]).into_owned(),
+    })
+}
+
+pub fn send_frame(port: &mut Box<dyn serialport::SerialPort>, seq: u16, frame: &[u8], ack_timeout: Duration) -> Result<(), String> {
+    let message = encode_message(MSG_FRAME, seq, frame);
+
+    let mut failure = String::new();
+    for _ in 0..FRAME_ATTEMPTS {
+        write_message(port, &message)?;
+
+        let attempt = with_read_timeout(port, ack_timeout, |port| loop {
+            let reply = match read_message(port) {
+                Ok(reply) => reply,
+                Err(ReadError::Io(e)) => break Err(e),
+                Err(e) => break Ok(Err(String::from(e))),
+            };
+            if reply.seq != seq {
+                continue;
+            }
+
+            match reply.kind {
+                MSG_ACK => break Ok(Ok(())),
+                MSG_NAK => break Ok(Err(String::from("Device rejected frame"))),
+                _ => continue,
+            }
+        })?;
+
+        match attempt {
+            Ok(()) => return Ok(()),
+            Err(e) => failure = e,
+        }
+    }
+
+    Err(format!("Frame {} failed after {} attempts: {}", seq, FRAME_ATTEMPTS, failure))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn crc32_matches_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn encodes_header_payload_and_crc() {
+        let message = encode_message(MSG_FRAME, 0x0102, &[0xaa, 0xbb]);
+
+        assert_eq!(&message[..HEADER_LEN], &[0x4e, 0x4c, MSG_FRAME, 0x02, 0x01, 0x02, 0x00, 0x00, 0x00]);
+        assert_eq!(&message[HEADER_LEN..HEADER_LEN + 2], &[0xaa, 0xbb]);
+        assert_eq!(&message[HEADER_LEN + 2..], &crc32(&message[..HEADER_LEN + 2]).to_le_bytes());
+    }
+
+    #[test]
+    fn decodes_encoded_message() {
+        let mut port = Cursor::new(encode_message(MSG_HELLO_REPLY, 7, &[30, 0, 32, 0, b'v', b'1']));
+        let message = read_message(&mut port).unwrap();
+
+        assert_eq!(message.kind, MSG_HELLO_REPLY);
+        assert_eq!(message.seq, 7);
+        assert_eq!(message.payload, vec![30, 0, 32, 0, b'v', b'1']);
+    }
+
+    #[test]
+    fn resyncs_past_garbage() {
+        let mut bytes = b"junk\x00NN".to_vec();
+        bytes.extend(encode_message(MSG_ACK, 3, &[]));
+        bytes.extend(encode_message(MSG_NAK, 4, &[]));
+        let mut port = Cursor::new(bytes);
+
+        let first = read_message(&mut port).unwrap();
+        assert_eq!((first.kind, first.seq), (MSG_ACK, 3));
+
+        let second = read_message(&mut port).unwrap();
+        assert_eq!((second.kind, second.seq), (MSG_NAK, 4));
+    }
+
+    #[test]
+    fn rejects_bad_crc() {
+        let mut bytes = encode_message(MSG_ACK, 1, &[1, 2, 3]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        assert!(matches!(read_message(&mut Cursor::new(bytes)), Err(ReadError::Corrupt(_))));
+    }
+
+    #[test]
+    fn rejects_corrupted_payload() {
+        let mut bytes = encode_message(MSG_ACK, 1, &[1, 2, 3]);
+        bytes[HEADER_LEN] ^= 0x01;
+
+        assert!(matches!(read_message(&mut Cursor::new(bytes)), Err(ReadError::Corrupt(_))));
+    }
+
+    #[test]
+    fn reports_read_timeout() {
+        struct TimedOut;
+        impl Read for TimedOut {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::TimedOut.into())
+            }
+        }
+
+        assert!(matches!(read_message(&mut TimedOut), Err(ReadError::Timeout)));
+    }
+}
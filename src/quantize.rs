@@ -0,0 +1,295 @@
+use serde::{Deserialize, Serialize};
+
+const MAX_COLORS: u16 = 256;
+
+const BAYER_SIZE: usize = 8;
+
+const BAYER: [[u8; BAYER_SIZE]; BAYER_SIZE] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+fn default_colors() -> u16 {
+    16
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteKind {
+    #[default]
+    None,
+    Primary,
+    Web,
+    Grayscale,
+    Adaptive,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DitherMode {
+    #[default]
+    None,
+    Bayer,
+    FloydSteinberg,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+pub struct QuantizeOptions {
+    #[serde(default)]
+    pub palette: PaletteKind,
+    #[serde(default = "default_colors")]
+    pub colors: u16,
+    #[serde(default)]
+    pub dither: DitherMode,
+}
+impl QuantizeOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(2..=MAX_COLORS).contains(&self.colors) {
+            return Err(format!("Palette size must be between 2 and {}, got {}", MAX_COLORS, self.colors));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.palette == PaletteKind::None
+    }
+
+    fn build_palette(&self, images: &[image::RgbaImage]) -> Vec<[u8; 3]> {
+        match self.palette {
+            PaletteKind::None => Vec::new(),
+            PaletteKind::Primary => cube_palette(2),
+            PaletteKind::Web => cube_palette(6),
+            PaletteKind::Grayscale => (0..self.colors)
+                .map(|level| {
+                    let value = (level as f32 * 255.0 / (self.colors - 1) as f32).round() as u8;
+                    [value, value, value]
+                })
+                .collect(),
+            PaletteKind::Adaptive => median_cut(images, self.colors as usize),
+        }
+    }
+
+    fn spread(&self, palette: &[[u8; 3]]) -> f32 {
+        match self.palette {
+            PaletteKind::None => 0.0,
+            PaletteKind::Primary => 255.0,
+            PaletteKind::Web => 51.0,
+            PaletteKind::Grayscale => 255.0 / (self.colors - 1) as f32,
+            PaletteKind::Adaptive => 255.0 / (palette.len() as f32).cbrt().max(1.0),
+        }
+    }
+}
+impl std::default::Default for QuantizeOptions {
+    fn default() -> Self {
+        QuantizeOptions{
+            palette: PaletteKind::default(),
+            colors: default_colors(),
+            dither: DitherMode::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct QuantizeQuery {
+    pub palette: Option<PaletteKind>,
+    pub colors: Option<u16>,
+    pub dither: Option<DitherMode>,
+}
+impl QuantizeQuery {
+    pub fn apply(&self, options: QuantizeOptions) -> Result<QuantizeOptions, String> {
+        let options = QuantizeOptions{
+            palette: self.palette.unwrap_or(options.palette),
+            colors: self.colors.unwrap_or(options.colors),
+            dither: self.dither.unwrap_or(options.dither),
+        };
+
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+fn cube_palette(levels: u32) -> Vec<[u8; 3]> {
+    let level = |i: u32| (i * 255 / (levels - 1)) as u8;
+
+    let mut palette = Vec::new();
+    for r in 0..levels {
+        for g in 0..levels {
+            for b in 0..levels {
+                palette.push([level(r), level(g), level(b)]);
+            }
+        }
+    }
+
+    palette
+}
+
+fn channel_range(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let min = colors.iter().map(|color| color[c]).min().unwrap_or(0);
+            let max = colors.iter().map(|color| color[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn average(colors: &[[u8; 3]]) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+    for color in colors {
+        for c in 0..3 {
+            sum[c] += color[c] as u64;
+        }
+    }
+
+    sum.map(|value| (value as f64 / colors.len() as f64).round() as u8)
+}
+
+// Samples every opaque pixel of every frame, so an animation shares one
+// palette and colors don't shift between frames.
+fn median_cut(images: &[image::RgbaImage], size: usize) -> Vec<[u8; 3]> {
+    let colors: Vec<[u8; 3]> = images.iter()
+        .flat_map(|img| img.pixels())
+        .filter(|pixel| pixel[3] > 0)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    if colors.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    let mut boxes = vec![colors];
+    while boxes.len() < size {
+        let widest = boxes.iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| (i, channel_range(colors)))
+            .max_by_key(|&(_, (_, range))| range);
+
+        let (i, channel) = match widest {
+            Some((i, (channel, range))) if range > 0 => (i, channel),
+            _ => break,
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|color| color[channel]);
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|colors| average(colors)).collect()
+}
+
+fn nearest(palette: &[[u8; 3]], rgb: [f32; 3]) -> [u8; 3] {
+    *palette.iter()
+        .min_by(|a, b| distance(a, rgb).total_cmp(&distance(b, rgb)))
+        .unwrap()
+}
+
+fn distance(color: &[u8; 3], rgb: [f32; 3]) -> f32 {
+    let dr = color[0] as f32 - rgb[0];
+    let dg = color[1] as f32 - rgb[1];
+    let db = color[2] as f32 - rgb[2];
+
+    2.0 * dr * dr + 4.0 * dg * dg + 3.0 * db * db
+}
+
+fn bayer_threshold(x: u32, y: u32) -> f32 {
+    let value = BAYER[y as usize % BAYER_SIZE][x as usize % BAYER_SIZE];
+
+    (value as f32 + 0.5) / (BAYER_SIZE * BAYER_SIZE) as f32 - 0.5
+}
+
+fn rgb_of(pixel: &image::Rgba<u8>) -> [f32; 3] {
+    [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]
+}
+
+fn quantize_plain(img: &mut image::RgbaImage, palette: &[[u8; 3]]) {
+    for pixel in img.pixels_mut() {
+        let rgb = nearest(palette, rgb_of(pixel));
+        pixel.0[..3].copy_from_slice(&rgb);
+    }
+}
+
+// The threshold only depends on the pixel position, so the pattern is
+// inherently stable across frames.
+fn quantize_bayer(img: &mut image::RgbaImage, palette: &[[u8; 3]], spread: f32) {
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let offset = bayer_threshold(x, y) * spread;
+        let rgb = nearest(palette, rgb_of(pixel).map(|value| value + offset));
+        pixel.0[..3].copy_from_slice(&rgb);
+    }
+}
+
+// Pixels whose source color is unchanged from the previous frame keep their
+// previous output, so the diffusion pattern doesn't crawl over static areas.
+fn quantize_floyd_steinberg(img: &mut image::RgbaImage, source: &image::RgbaImage, palette: &[[u8; 3]], previous: Option<(&image::RgbaImage, &image::RgbaImage)>) {
+    let (width, height) = img.dimensions();
+    let previous = previous.filter(|(prev_source, _)| prev_source.dimensions() == source.dimensions());
+
+    let mut error = vec![[0.0f32; 3]; (width as usize + 2) * 2];
+    let row = width as usize + 2;
+
+    for y in 0..height {
+        let (current, next) = error.split_at_mut(row);
+        next.fill([0.0; 3]);
+
+        for x in 0..width {
+            let pixel = source.get_pixel(x, y);
+            if pixel[3] == 0 {
+                continue;
+            }
+
+            let i = x as usize + 1;
+            let value = [0, 1, 2].map(|c| (pixel[c] as f32 + current[i][c]).clamp(0.0, 255.0));
+
+            let rgb = match previous {
+                Some((prev_source, prev_output)) if prev_source.get_pixel(x, y) == pixel => {
+                    let prev = prev_output.get_pixel(x, y);
+                    [prev[0], prev[1], prev[2]]
+                },
+                _ => nearest(palette, value),
+            };
+            img.get_pixel_mut(x, y).0[..3].copy_from_slice(&rgb);
+
+            for c in 0..3 {
+                let err = value[c] - rgb[c] as f32;
+                current[i + 1][c] += err * 7.0 / 16.0;
+                next[i - 1][c] += err * 3.0 / 16.0;
+                next[i][c] += err * 5.0 / 16.0;
+                next[i + 1][c] += err / 16.0;
+            }
+        }
+
+        error.copy_within(row.., 0);
+    }
+}
+
+pub fn quantize_images(images: &mut [image::RgbaImage], options: &QuantizeOptions) {
+    if options.is_none() {
+        return;
+    }
+
+    let palette = options.build_palette(images);
+    let spread = options.spread(&palette);
+
+    let mut previous: Option<(image::RgbaImage, image::RgbaImage)> = None;
+    for img in images.iter_mut() {
+        match options.dither {
+            DitherMode::None => quantize_plain(img, &palette),
+            DitherMode::Bayer => quantize_bayer(img, &palette, spread),
+            DitherMode::FloydSteinberg => {
+                let source = img.clone();
+                quantize_floyd_steinberg(img, &source, &palette, previous.as_ref().map(|(source, output)| (source, output)));
+                previous = Some((source, img.clone()));
+            },
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{Config, OutputKind, SerialConfig};
+use crate::device::{self, DeviceSelector, DeviceStatus, SharedDeviceStatus, SharedPortWatch};
+use crate::frame::Frame;
+
+const MEMORY_SINK_CAPACITY: usize = 256;
+
+pub type MemoryFrames = Arc<Mutex<VecDeque<Frame>>>;
+
+pub trait DisplaySink: Send {
+    fn name(&self) -> &'static str;
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), String>;
+
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    fn reconnect(&mut self) -> bool {
+        true
+    }
+}
+
+pub struct Output {
+    pub sink: Box<dyn DisplaySink>,
+    pub status: SharedDeviceStatus,
+    pub ports: Option<SharedPortWatch>,
+    pub recorded_frames: Option<MemoryFrames>,
+}
+
+pub struct SerialSink {
+    connection: device::Connection,
+}
+impl SerialSink {
+    pub fn open(selector: DeviceSelector, watch: SharedPortWatch, serial: SerialConfig, status: SharedDeviceStatus) -> Self {
+        SerialSink{ connection: device::Connection::new(selector, watch, serial, status) }
+    }
+}
+impl DisplaySink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        self.connection.write_frame(frame)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connection.is_connected()
+    }
+
+    fn reconnect(&mut self) -> bool {
+        self.connection.reconnect()
+    }
+}
+
+pub struct NullSink;
+impl DisplaySink for NullSink {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn write_frame(&mut self, _frame: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub struct FileSink {
+    fh: File,
+}
+impl FileSink {
+    pub fn create(path: &str) -> Result<Self, String> {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(fh) => Ok(FileSink{ fh }),
+            Err(e) => Err(format!("Cannot open recording file {}: {}", path, e)),
+        }
+    }
+}
+impl DisplaySink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        if let Err(e) = self.fh.write_all(frame) {
+            return Err(format!("Failed to record frame: {}", e));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct MemorySink {
+    frames: MemoryFrames,
+}
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink{ frames: Arc::new(Mutex::new(VecDeque::new())) }
+    }
+
+    pub fn frames(&self) -> MemoryFrames {
+        self.frames.clone()
+    }
+}
+impl DisplaySink for MemorySink {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() == MEMORY_SINK_CAPACITY {
+            frames.pop_front();
+        }
+        frames.push_back(frame.to_vec());
+
+        Ok(())
+    }
+}
+
+pub fn open_sink(cfg: &Config) -> Result<Output, String> {
+    let output = cfg.output_kind();
+    let status = Arc::new(Mutex::new(DeviceStatus{
+        output: output.name(),
+        device: None,
+        connected: true,
+        info: None,
+        last_error: None,
+    }));
+    let mut ports = None;
+    let mut recorded_frames = None;
+
+    let sink: Box<dyn DisplaySink> = match output {
+        OutputKind::Serial => {
+            let selector = match (&cfg.device, &cfg.usb) {
+                (Some(device), _) => DeviceSelector::Path(device.clone()),
+                (None, Some(usb)) => DeviceSelector::Usb(usb.clone()),
+                (None, None) => return Err(String::from("Serial output requires a device or usb match")),
+            };
+            let watch = device::watch_ports(selector.clone());
+            ports = Some(watch.clone());
+
+            Box::new(SerialSink::open(selector, watch, cfg.serial.clone(), status.clone()))
+        },
+        OutputKind::Null => Box::new(NullSink),
+        OutputKind::File => match &cfg.record {
+            Some(path) => Box::new(FileSink::create(path)?),
+            None => return Err(String::from("File output requires a record path")),
+        },
+        OutputKind::Memory => {
+            let sink = MemorySink::new();
+            recorded_frames = Some(sink.frames());
+
+            Box::new(sink)
+        },
+    };
+
+    Ok(Output{ sink, status, ports, recorded_frames })
+}
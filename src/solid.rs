@@ -1,9 +1,50 @@
-use crate::frame::{IntoFrameSpec, frame_from_rgb};
+use serde::Deserialize;
+
+use crate::color::{self, ColorSpace, Easing};
+use crate::frame::{DEFAULT_FRAME_DURATION, Frame, Frames, IntoFrameSpec, TimedFrame, frame_from_rgb};
+
+const MAX_FADE_MS: u64 = 60_000;
 
 pub type SmoothSolidColor = Vec<[u8; 3]>;
 
+#[derive(Deserialize)]
+pub struct SolidFade {
+    pub color: [u8; 3],
+    #[serde(default)]
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub easing: Easing,
+    #[serde(default)]
+    pub space: ColorSpace,
+}
+impl SolidFade {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.duration_ms > MAX_FADE_MS {
+            return Err(format!("Fade duration must be at most {} ms, got {}", MAX_FADE_MS, self.duration_ms));
+        }
+
+        Ok(())
+    }
+}
+
 pub fn make_frame<F: IntoFrameSpec>(frame_spec: F, r: u8, g: u8, b: u8) -> Vec<u8> {
     let frame_spec = frame_spec.into_framespec();
 
     frame_from_rgb(frame_spec, r, g, b)
 }
+
+pub fn fade_frames(from: &Frame, to: &Frame, duration_ms: u64, easing: Easing, space: ColorSpace) -> Frames {
+    let steps = (duration_ms / DEFAULT_FRAME_DURATION.as_millis() as u64).max(1);
+
+    (1..=steps)
+        .map(|step| {
+            let t = easing.apply(step as f32 / steps as f32);
+            let frame = from.chunks_exact(3)
+                .zip(to.chunks_exact(3))
+                .flat_map(|(from, to)| color::interpolate([from[0], from[1], from[2]], [to[0], to[1], to[2]], t, space))
+                .collect();
+
+            TimedFrame::new(frame)
+        })
+        .collect()
+}
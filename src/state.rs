@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+use std::fs::{read, read_to_string, rename, write};
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+use crate::adjust::Adjustments;
+use crate::imgops::ResampleOptions;
+use crate::playback::PlaybackOptions;
+use crate::quantize::QuantizeOptions;
+
+fn default_brightness() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SavedContent {
+    Effect { name: String, params: serde_json::Value },
+    Image {
+        playback: PlaybackOptions,
+        #[serde(default)]
+        resample: ResampleOptions,
+        #[serde(default)]
+        adjust: Adjustments,
+        #[serde(default)]
+        quantize: QuantizeOptions,
+    },
+    SolidColor { color: [u8; 3] },
+    Template { name: String, playback: PlaybackOptions },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SavedState {
+    #[serde(default = "default_brightness")]
+    pub brightness: f32,
+    #[serde(default)]
+    pub content: Option<SavedContent>,
+}
+impl std::default::Default for SavedState {
+    fn default() -> Self {
+        SavedState{
+            brightness: default_brightness(),
+            content: None,
+        }
+    }
+}
+
+fn image_path(path: &Path) -> PathBuf {
+    let mut image_path = PathBuf::from(path);
+    image_path.set_extension("image");
+
+    image_path
+}
+
+fn replace_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    if let Err(e) = write(&tmp_path, data) {
+        return Err(format!("Failed to write {}: {}", tmp_path.display(), e));
+    }
+
+    match rename(&tmp_path, path) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to replace {}: {}", path.display(), e)),
+    }
+}
+
+pub fn valid_or_default<T: Default>(options: T, validate: fn(&T) -> Result<(), String>) -> T {
+    match validate(&options) {
+        Ok(()) => options,
+        Err(e) => {
+            warn!("Ignoring invalid saved options: {}", e);
+            T::default()
+        },
+    }
+}
+
+pub fn read_state(path: &Path) -> SavedState {
+    let content = match read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return SavedState::default(),
+    };
+
+    match serde_json::from_str(&content) {
+        Ok(state) => state,
+        Err(e) => {
+            warn!("Ignoring unreadable state file {}: {}", path.display(), e);
+            SavedState::default()
+        },
+    }
+}
+
+pub fn write_state(path: &Path, state: &SavedState) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(state).unwrap();
+
+    match replace_file(path, json.as_bytes()) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to save state file: {}", e)),
+    }
+}
+
+pub fn read_image(path: &Path) -> Result<Vec<u8>, String> {
+    match read(image_path(path)) {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => Err(format!("Failed to read saved image: {}", e)),
+    }
+}
+
+pub fn write_image(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    match replace_file(&image_path(path), bytes) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to save image: {}", e)),
+    }
+}
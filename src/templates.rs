@@ -1,16 +1,67 @@
-use std::fs::{read_dir, remove_file, File};
-use std::io::{Read, Write};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{create_dir_all, read, read_dir, read_to_string, remove_file, write, File};
+use std::io::{ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
 
-use crate::imgops;
+use crate::adjust::Adjustments;
+use crate::imgops::{self, ResampleOptions};
+use crate::playback::PlaybackOptions;
+use crate::quantize::QuantizeOptions;
+use crate::state;
 
-pub fn delete_template(path: &Path, name: String) -> Result<(), String> {
+const META_DIR: &str = ".meta";
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct TemplateMeta {
+    #[serde(default)]
+    pub playback: PlaybackOptions,
+    #[serde(default)]
+    pub resample: ResampleOptions,
+    #[serde(default)]
+    pub adjust: Adjustments,
+    #[serde(default)]
+    pub quantize: QuantizeOptions,
+}
+
+fn meta_path(path: &Path, name: &str) -> PathBuf {
     let mut file_path = PathBuf::from(path);
-    file_path.push(name);
+    file_path.push(META_DIR);
+    file_path.push(format!("{}.json", name));
+
+    file_path
+}
+
+fn original_path(path: &Path, name: &str) -> PathBuf {
+    let mut file_path = PathBuf::from(path);
+    file_path.push(META_DIR);
+    file_path.push(format!("{}.orig", name));
+
+    file_path
+}
 
+fn remove_if_exists(file_path: PathBuf) -> std::io::Result<()> {
     match remove_file(file_path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+pub fn delete_template(path: &Path, name: String) -> Result<(), String> {
+    let mut file_path = PathBuf::from(path);
+    file_path.push(&name);
+
+    if let Err(e) = remove_file(file_path) {
+        return Err(format!("Failed to delete template {}", e));
+    }
+
+    if let Err(e) = remove_if_exists(meta_path(path, &name)) {
+        return Err(format!("Failed to delete template metadata {}", e));
+    }
+
+    match remove_if_exists(original_path(path, &name)) {
         Ok(()) => Ok(()),
-        Err(e) => Err(format!("Failed to delete template {}", e)),
+        Err(e) => Err(format!("Failed to delete template original {}", e)),
     }
 }
 
@@ -19,22 +70,17 @@ pub fn list_templates(path: &Path) -> Result<Vec<String>, String> {
         Ok(entries) => {
             let mut templates = Vec::new();
 
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let epath = entry.path();
-                    if !epath.is_file() {
-                        continue;
-                    }
+            for entry in entries.flatten() {
+                let epath = entry.path();
+                if !epath.is_file() {
+                    continue;
+                }
 
-                    match epath.file_name() {
-                        Some(file_name) => {
-                            let file_name: String = file_name.to_string_lossy().into();
+                if let Some(file_name) = epath.file_name() {
+                    let file_name: String = file_name.to_string_lossy().into();
 
-                            if imgops::is_image(&epath) {
-                                templates.push(file_name);
-                            }
-                        },
-                        None => (),
+                    if imgops::is_image(&epath) {
+                        templates.push(file_name);
                     }
                 }
             }
@@ -75,3 +121,58 @@ pub fn write_template(path: &Path, name: String, data: &[u8]) -> Result<(), Stri
         Err(e) => Err(format!("Failed to write template file: {}", e)),
     }
 }
+
+pub fn read_template_meta(path: &Path, name: &str) -> Result<TemplateMeta, String> {
+    let content = match read_to_string(meta_path(path, name)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(TemplateMeta::default()),
+        Err(e) => return Err(format!("Failed to read template metadata: {}", e)),
+    };
+
+    let meta: TemplateMeta = match serde_json::from_str(&content) {
+        Ok(meta) => meta,
+        Err(e) => return Err(format!("Failed to parse template metadata: {}", e)),
+    };
+
+    Ok(TemplateMeta{
+        playback: state::valid_or_default(meta.playback, PlaybackOptions::validate),
+        resample: meta.resample,
+        adjust: state::valid_or_default(meta.adjust, Adjustments::validate),
+        quantize: state::valid_or_default(meta.quantize, QuantizeOptions::validate),
+    })
+}
+
+pub fn write_template_meta(path: &Path, name: &str, meta: &TemplateMeta) -> Result<(), String> {
+    let file_path = meta_path(path, name);
+    if let Some(dir) = file_path.parent()
+        && let Err(e) = create_dir_all(dir) {
+        return Err(format!("Failed to create template metadata directory: {}", e));
+    }
+
+    let json = serde_json::to_string_pretty(meta).unwrap();
+    match write(file_path, json) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to write template metadata: {}", e)),
+    }
+}
+
+pub fn read_template_original(path: &Path, name: &str) -> Result<Vec<u8>, String> {
+    match read(original_path(path, name)) {
+        Ok(data) => Ok(data),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(format!("Template {} has no stored original image", name)),
+        Err(e) => Err(format!("Failed to read template original: {}", e)),
+    }
+}
+
+pub fn write_template_original(path: &Path, name: &str, data: &[u8]) -> Result<(), String> {
+    let file_path = original_path(path, name);
+    if let Some(dir) = file_path.parent()
+        && let Err(e) = create_dir_all(dir) {
+        return Err(format!("Failed to create template metadata directory: {}", e));
+    }
+
+    match write(file_path, data) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to write template original: {}", e)),
+    }
+}
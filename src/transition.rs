@@ -0,0 +1,127 @@
+use serde::Deserialize;
+
+use std::time::{Duration, Instant};
+
+use crate::frame::{Canvas, Frame};
+
+const MAX_TRANSITION_MS: u64 = 60_000;
+
+fn default_transition_ms() -> u64 {
+    500
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionKind {
+    #[default]
+    None,
+    Crossfade,
+    Wipe,
+    Slide,
+    Dissolve,
+    Iris,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct TransitionOptions {
+    #[serde(default)]
+    pub transition: TransitionKind,
+    #[serde(default = "default_transition_ms")]
+    pub transition_ms: u64,
+}
+impl TransitionOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.transition_ms > MAX_TRANSITION_MS {
+            return Err(format!("Transition duration must be at most {} ms, got {}", MAX_TRANSITION_MS, self.transition_ms));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.transition == TransitionKind::None || self.transition_ms == 0
+    }
+}
+impl std::default::Default for TransitionOptions {
+    fn default() -> Self {
+        TransitionOptions{
+            transition: TransitionKind::default(),
+            transition_ms: default_transition_ms(),
+        }
+    }
+}
+
+fn dissolve_threshold(led: usize) -> f32 {
+    let mut hash = led as u32 ^ 0x9e37_79b9;
+    hash = (hash ^ (hash >> 16)).wrapping_mul(0x045d_9f3b);
+    hash = (hash ^ (hash >> 16)).wrapping_mul(0x045d_9f3b);
+    hash ^= hash >> 16;
+
+    hash as f32 / u32::MAX as f32
+}
+
+pub struct Transition {
+    kind: TransitionKind,
+    from: Frame,
+    start: Instant,
+    duration: Duration,
+}
+impl Transition {
+    pub fn new(options: &TransitionOptions, from: Frame, now: Instant) -> Self {
+        Transition{
+            kind: options.transition,
+            from,
+            start: now,
+            duration: Duration::from_millis(options.transition_ms),
+        }
+    }
+
+    pub fn is_done(&self, now: Instant) -> bool {
+        now - self.start >= self.duration
+    }
+
+    fn progress(&self, now: Instant) -> f32 {
+        ((now - self.start).as_secs_f32() / self.duration.as_secs_f32()).clamp(0.0, 1.0)
+    }
+
+    pub fn render(&self, canvas: &Canvas, to: &Frame, now: Instant) -> Frame {
+        let t = self.progress(now);
+        if self.from.len() != to.len() {
+            return to.clone();
+        }
+
+        let width = canvas.width as f32;
+        let height = canvas.height as f32;
+        let max_radius = (width * width + height * height).sqrt() / 2.0;
+
+        let mut frame = Vec::with_capacity(to.len());
+        for (led, &(x, y)) in canvas.positions.iter().enumerate() {
+            let from = [self.from[led * 3], self.from[led * 3 + 1], self.from[led * 3 + 2]];
+            let target = [to[led * 3], to[led * 3 + 1], to[led * 3 + 2]];
+
+            let rgb = match self.kind {
+                TransitionKind::None => target,
+                TransitionKind::Crossfade => [0, 1, 2].map(|c| (from[c] as f32 + (target[c] as f32 - from[c] as f32) * t).round() as u8),
+                TransitionKind::Wipe => if (x as f32) < t * width { target } else { from },
+                TransitionKind::Slide => {
+                    let shifted = x + (t * width) as u32;
+                    if shifted < canvas.width {
+                        canvas.pixel(&self.from, shifted, y)
+                    } else {
+                        canvas.pixel(to, shifted - canvas.width, y)
+                    }
+                },
+                TransitionKind::Dissolve => if dissolve_threshold(led) < t { target } else { from },
+                TransitionKind::Iris => {
+                    let dx = x as f32 + 0.5 - width / 2.0;
+                    let dy = y as f32 + 0.5 - height / 2.0;
+                    if (dx * dx + dy * dy).sqrt() < t * max_radius { target } else { from }
+                },
+            };
+
+            frame.extend_from_slice(&rgb);
+        }
+
+        frame
+    }
+}